@@ -0,0 +1,184 @@
+use crate::config::Config;
+use crate::file_parser::FileParser;
+use crate::request::Request;
+use crate::response::Response;
+use crate::router;
+use crate::shutdown::ShutdownHandle;
+use crate::state::State;
+use crate::static_files::StaticMount;
+use crate::{Middleware, PanicHook, Route};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Everything the worker threads need to answer requests. The app is built once when the server is bound and shared between the workers
+pub struct App {
+    // Contains all the routes for http resources on the server, keyed by path and then by request method
+    pub routes: HashMap<String, HashMap<String, Route>>,
+    // Contains all the middleware for the servers resources, sorted by path length
+    pub middleware: Vec<(String, Middleware)>,
+    // Directories whose files are served when no route matches
+    pub static_mounts: Vec<StaticMount>,
+    // Application state that is made avalible to every request
+    pub state: Arc<State>,
+    // The content types used when serving files
+    pub file_parser: Arc<FileParser>,
+    // Body size limits of routes that override the default limit, keyed by path
+    pub body_limits: HashMap<String, usize>,
+    // Server settings such as timeouts
+    pub config: Config,
+    // Reports panics in routes and middleware, they are printed if there is no hook
+    pub panic_hook: Option<PanicHook>,
+    // Tells the workers to close connections when the server is shutting down
+    pub shutdown: ShutdownHandle,
+}
+
+impl App {
+    /// The largest request body accepted for the request, the limit set for the matching route or the default limit
+    pub fn body_limit(&self, request: &Request) -> usize {
+        let request_wo_params = request.url.split('?').next().unwrap_or_default();
+        let request_route = router::normalize_path(request_wo_params);
+        match router::find_route(&self.body_limits, &request_route) {
+            Some((limit, _path_params)) => *limit,
+            None => self.config.max_body_size,
+        }
+    }
+
+    /// Routes the request through the middleware and the matching route or static file, then returns the response that should be written to the client
+    pub fn handle_request(&self, mut request: Request, mut response: Response) -> Response {
+        request.state = Arc::clone(&self.state);
+        request.file_parser = Arc::clone(&self.file_parser);
+        // Remove params
+        let request_wo_params = match request.url.split('?').next() {
+            Some(url) => url,
+            None => {
+                response.status(400);
+                return response;
+            }
+        };
+        // Remove trailing / so that pathing is agnostic towards /example/ or /example
+        let mut request_route = router::normalize_path(request_wo_params);
+        let handler = match router::find_route(&self.routes, &request_route) {
+            Some((methods, path_params)) => match router::find_method(methods, &request.method) {
+                Ok(function) => {
+                    request.path_params = path_params;
+                    Handler::Route(function)
+                }
+                Err(allowed) => {
+                    response.status(405);
+                    response.header("allow", allowed.join(", "));
+                    return response;
+                }
+            },
+            None => {
+                // Files in the static directories are only looked up if no route matches
+                let mount = match self.find_static_mount(request_wo_params) {
+                    Some(mount) => mount,
+                    None => return response,
+                };
+                if request.method != "GET" && request.method != "HEAD" {
+                    if mount.contains(request_wo_params) {
+                        response.status(405);
+                        response.header("allow", "GET, HEAD");
+                    }
+                    return response;
+                }
+                // Static files are looked up by their decoded path, so middleware has to match that path too or /%73ecret would bypass middleware for /secret
+                request_route = match mount.canonical_path(request_wo_params) {
+                    Some(path) => path,
+                    None => return response,
+                };
+                Handler::Static(mount)
+            }
+        };
+        // Route through middleware
+        for mid in &self.middleware {
+            if mid.0.len() > request_route.len() {
+                break;
+            };
+            if mid.0 == request_route[..mid.0.len()] {
+                let answer = mid.1(request, response);
+                response = answer.1;
+                // If the middleware rejects the request we return the response
+                if !answer.2 {
+                    return response;
+                }
+                request = answer.0;
+            }
+        }
+        return match handler {
+            Handler::Route(function) => function(request, response),
+            Handler::Static(mount) => mount.serve(&request, response),
+        };
+    }
+
+    /// Finds the static directory with the longest prefix that the url path lies under
+    fn find_static_mount(&self, url_path: &str) -> Option<&StaticMount> {
+        self.static_mounts
+            .iter()
+            .filter(|mount| mount.matches(url_path))
+            .max_by_key(|mount| mount.prefix().len())
+    }
+}
+
+/// What answers a request after it has passed through the middleware
+enum Handler<'a> {
+    Route(&'a Route),
+    Static(&'a StaticMount),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static ASSETS: &[(&str, &[u8])] = &[("secret/key.txt", b"key"), ("public.txt", b"public")];
+
+    fn app() -> App {
+        let deny: Middleware = Arc::new(|request, mut response| {
+            response.status(403);
+            return (request, response, false);
+        });
+        return App {
+            routes: HashMap::new(),
+            middleware: vec![(String::from("/secret"), deny)],
+            static_mounts: vec![StaticMount::embedded("/", ASSETS)],
+            state: Arc::new(State::new()),
+            file_parser: Arc::new(FileParser::new()),
+            body_limits: HashMap::new(),
+            config: Config::default(),
+            panic_hook: None,
+            shutdown: ShutdownHandle::new(),
+        };
+    }
+
+    fn get(app: &App, url: &str) -> Response {
+        let request = Request::new(
+            String::from(url),
+            HashMap::new(),
+            Vec::new(),
+            String::from("1.1"),
+            String::from("GET"),
+            HashMap::new(),
+        );
+        return app.handle_request(request, Response::new(404, Vec::new(), HashMap::new()));
+    }
+
+    #[test]
+    fn applies_middleware_to_static_files() {
+        let app = app();
+        assert_eq!(get(&app, "/public.txt").status, 200);
+        assert_eq!(get(&app, "/secret/key.txt").status, 403);
+    }
+
+    #[test]
+    fn applies_middleware_to_encoded_static_paths() {
+        let app = app();
+        for url in [
+            "/%73ecret/key.txt",
+            "//secret/key.txt",
+            "/./secret/key.txt",
+            "/secret//key.txt?a=b",
+        ] {
+            assert_eq!(get(&app, url).status, 403, "url {}", url);
+        }
+    }
+}
@@ -0,0 +1,64 @@
+use std::time::Duration;
+
+/// Server settings that are shared with the worker threads
+#[derive(Clone)]
+pub struct Config {
+    /// How long an idle keep-alive connection is kept open while waiting for the next request
+    pub keep_alive_timeout: Duration,
+    /// How long a new connection can stay silent before it has to start sending its first request
+    pub idle_timeout: Duration,
+    /// How long the client has to send the request line and headers, counted from the first byte of the request
+    pub header_timeout: Duration,
+    /// How long the client has to send the body of a request after the headers
+    pub body_timeout: Duration,
+    /// The longest request line that is accepted, longer request lines are answered with 414 URI Too Long
+    pub max_request_line: usize,
+    /// The most headers a request can have, more are answered with 431 Request Header Fields Too Large
+    pub max_header_count: usize,
+    /// The most bytes all header lines of a request can take up together, more are answered with 431 Request Header Fields Too Large
+    pub max_header_bytes: usize,
+    /// The largest request body that is accepted by routes without their own limit, larger bodies are answered with 413 Content Too Large
+    pub max_body_size: usize,
+    /// How many accepted connections can wait for a free worker
+    pub queue_depth: usize,
+    /// The most connections that are open at the same time, counting both the connections being handled and those waiting in the queue
+    pub max_connections: Option<usize>,
+    /// What happens to new connections when the queue is full or there are too many connections
+    pub backpressure: Backpressure,
+    /// Whether requests are read by an epoll event loop, so that idle connections do not take up a worker thread
+    pub event_loop: bool,
+    /// How long bind waits for requests that are being handled to finish when the server shuts down
+    pub shutdown_timeout: Duration,
+    /// Whether the server shuts down when the process receives SIGINT or SIGTERM
+    pub shutdown_on_signals: bool,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        return Config {
+            keep_alive_timeout: Duration::from_secs(5),
+            idle_timeout: Duration::from_secs(10),
+            header_timeout: Duration::from_secs(10),
+            body_timeout: Duration::from_secs(30),
+            max_request_line: 8 * 1024,
+            max_header_count: 100,
+            max_header_bytes: 16 * 1024,
+            max_body_size: 10 * 1024 * 1024,
+            queue_depth: 128,
+            max_connections: None,
+            backpressure: Backpressure::Reject(1),
+            event_loop: false,
+            shutdown_timeout: Duration::from_secs(30),
+            shutdown_on_signals: false,
+        };
+    }
+}
+
+/// What the server does with new connections when every worker is busy and the queue of waiting connections is full, or the maximum amount of connections is reached
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Backpressure {
+    /// Answer with 503 Service Unavailable and a Retry-After header with the number of seconds the client should wait, then close the connection
+    Reject(u64),
+    /// Stop accepting connections until there is room, new connections wait in the backlog of the operating system
+    Block,
+}
@@ -0,0 +1,309 @@
+use crate::app::App;
+use crate::error::Error;
+use crate::http_parser::HttpParser;
+use crate::panic_report::{panic_message, HandlerPanic};
+use crate::request::Request;
+use crate::response::Response;
+use std::any::Any;
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, ErrorKind, Read};
+use std::net::TcpStream;
+use std::panic::{self, AssertUnwindSafe};
+use std::time::{Duration, Instant};
+
+/// How often idle keep-alive connections check if the server is shutting down
+const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Handles all the requests sent over a tcp connection.
+///
+/// Connections are kept alive between requests as long as the client wants it, HTTP/1.1 connections are persistent unless the client sends connection: close and HTTP/1.0 connections are closed unless the client sends connection: keep-alive. Idle connections are closed after the keep-alive timeout.
+///
+/// New connections that send no request within the idle timeout, and requests whose headers or body are not received within the header and body timeouts, are answered with 408 Request Timeout so that slow clients can not hold on to the worker threads
+pub fn handle_connection(stream: &TcpStream, app: &App) {
+    let five_seconds = Duration::new(5, 0);
+    match stream.set_write_timeout(Some(five_seconds)) {
+        Ok(_) => {}
+        Err(error) => println!("{}", error),
+    }
+    let mut reader = BufReader::new(DeadlineReader {
+        stream,
+        deadline: None,
+    });
+    let mut first_request = true;
+    loop {
+        // Wait for the next request, the connection is closed if the client closes it, stays idle for too long or the server shuts down
+        let wait_timeout = if first_request {
+            app.config.idle_timeout
+        } else {
+            app.config.keep_alive_timeout
+        };
+        match wait_for_request(stream, &mut reader, wait_timeout, first_request, app) {
+            Wait::Ready => {}
+            Wait::Closed => return,
+            Wait::TimedOut => {
+                // Only a new connection is told why it is closed, a client reusing an idle connection could mistake the 408 for the answer to a request it is sending
+                if first_request {
+                    write_error(stream, 408);
+                }
+                return;
+            }
+        }
+        first_request = false;
+
+        // The request line and headers have to arrive within the header timeout, the body within the body timeout after that
+        reader.get_mut().deadline = deadline(app.config.header_timeout);
+        let mut request = match HttpParser::parse(&mut reader, &app.config) {
+            Ok(request) => request,
+            Err(error) => {
+                println!("HTTP Parser Error: {}", error);
+                write_error(stream, error_status(&error));
+                return;
+            }
+        };
+        reader.get_mut().deadline = deadline(app.config.body_timeout);
+        let body_limit = app.body_limit(&request);
+        if let Err(error) =
+            HttpParser::read_body(&mut reader, &mut request, body_limit, &app.config)
+        {
+            println!("HTTP Parser Error: {}", error);
+            write_error(stream, error_status(&error));
+            return;
+        }
+        reader.get_mut().deadline = None;
+
+        if !respond(stream, request, app) {
+            return;
+        }
+    }
+}
+
+/// Answers a request that has been read in full and writes the response. Returns true if the connection should be kept open for the next request
+pub fn respond(stream: &TcpStream, request: Request, app: &App) -> bool {
+    let keep_alive_enabled = !app.config.keep_alive_timeout.is_zero();
+    let keep_alive = keep_alive_enabled && wants_keep_alive(&request);
+    let http_version = request.http_version.clone();
+    let head = request.method == "HEAD";
+    let method = request.method.clone();
+    let url = request.url.clone();
+    let response = Response::new(404, Vec::new(), HashMap::new());
+    let mut response = handle_request(app, request, response);
+    // Routes can close the connection by setting connection: close. HTTP/1.0 clients can only find the end of a streamed body without a length when the connection closes
+    let unframed_stream =
+        http_version == "1.0" && response.is_stream() && !response.has_header("content-length");
+    // Connections are closed after the response while the server shuts down
+    let keep_alive = keep_alive
+        && !app.shutdown.is_shutdown()
+        && !header_has_token(&response.headers, "connection", "close")
+        && !unframed_stream;
+    response.remove_header("connection");
+    if keep_alive {
+        if http_version == "1.0" {
+            response.header("connection", "keep-alive");
+        }
+    } else {
+        response.header("connection", "close");
+    }
+    // Streamed bodies run handler code while they are written. The response has already started when it panics, so the connection is closed instead of answering with 500
+    let written = panic::catch_unwind(AssertUnwindSafe(|| {
+        write_response(stream, response, &http_version, head)
+    }));
+    match written {
+        Ok(written) => return written && keep_alive,
+        Err(payload) => {
+            report_panic(app, method, url, payload.as_ref());
+            return false;
+        }
+    }
+}
+
+/// Lets the app answer the request. A panic in a route or middleware function is reported to the panic hook and answered with 500 Internal Server Error, so that it does not take the worker thread down
+fn handle_request(app: &App, request: Request, response: Response) -> Response {
+    let method = request.method.clone();
+    let url = request.url.clone();
+    // The request and response are moved into the handlers, so nothing they touched is used after a panic
+    match panic::catch_unwind(AssertUnwindSafe(|| app.handle_request(request, response))) {
+        Ok(response) => return response,
+        Err(payload) => {
+            report_panic(app, method, url, payload.as_ref());
+            return Response::new(500, Vec::new(), HashMap::new());
+        }
+    }
+}
+
+/// Reports a panic in handler code to the panic hook, or prints it if there is no hook
+fn report_panic(app: &App, method: String, url: String, payload: &(dyn Any + Send)) {
+    let report = HandlerPanic {
+        method,
+        url,
+        message: panic_message(payload),
+    };
+    match &app.panic_hook {
+        Some(hook) => hook(&report),
+        None => println!(
+            "Handler panicked on {} {}: {}",
+            report.method, report.url, report.message
+        ),
+    }
+}
+
+/// Answers a connection that can not be handled because the server is too busy with 503 Service Unavailable, telling the client to retry after the amount of seconds. The request is not read
+pub fn reject_connection(stream: &TcpStream, retry_after: u64) {
+    // The accept loop writes the response, so a client that does not read it can only hold the loop up briefly
+    match stream.set_write_timeout(Some(Duration::from_secs(1))) {
+        Ok(_) => {}
+        Err(error) => println!("{}", error),
+    }
+    let mut response = Response::new(503, Vec::new(), HashMap::new());
+    response.header("retry-after", retry_after.to_string());
+    response.header("connection", "close");
+    write_response(stream, response, "1.1", false);
+}
+
+/// Why waiting for the next request on a connection ended
+enum Wait {
+    // The first bytes of a request have arrived
+    Ready,
+    // The client closed the connection, or the server is shutting down
+    Closed,
+    // No request arrived within the timeout
+    TimedOut,
+}
+
+/// Waits until the next request starts arriving or the timeout runs out, a timeout of zero waits forever.
+///
+/// Idle connections are checked for a server shutdown every SHUTDOWN_POLL_INTERVAL, the first request of a connection is always waited for since the client has already connected
+fn wait_for_request(
+    stream: &TcpStream,
+    reader: &mut BufReader<DeadlineReader>,
+    timeout: Duration,
+    first_request: bool,
+    app: &App,
+) -> Wait {
+    let started = Instant::now();
+    reader.get_mut().deadline = None;
+    loop {
+        if !first_request && app.shutdown.is_shutdown() {
+            return Wait::Closed;
+        }
+        let remaining = if timeout.is_zero() {
+            None
+        } else {
+            match timeout.checked_sub(started.elapsed()) {
+                Some(remaining) if !remaining.is_zero() => Some(remaining),
+                _ => return Wait::TimedOut,
+            }
+        };
+        let read_timeout = if first_request {
+            remaining
+        } else {
+            Some(remaining.map_or(SHUTDOWN_POLL_INTERVAL, |remaining| {
+                remaining.min(SHUTDOWN_POLL_INTERVAL)
+            }))
+        };
+        match stream.set_read_timeout(read_timeout) {
+            Ok(_) => {}
+            Err(error) => {
+                println!("{}", error);
+                return Wait::Closed;
+            }
+        }
+        match reader.fill_buf() {
+            Ok(buffer) => {
+                if buffer.is_empty() {
+                    return Wait::Closed;
+                }
+                return Wait::Ready;
+            }
+            Err(error) if is_timeout(&error) => continue,
+            Err(_error) => return Wait::Closed,
+        }
+    }
+}
+
+/// Reads from a tcp stream, failing with TimedOut once the deadline has passed no matter how slowly the client sends the data. Without a deadline the read timeout of the stream is left as it is
+struct DeadlineReader<'a> {
+    stream: &'a TcpStream,
+    deadline: Option<Instant>,
+}
+
+impl Read for DeadlineReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if let Some(deadline) = self.deadline {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(io::Error::new(ErrorKind::TimedOut, "Request timed out"));
+            }
+            self.stream.set_read_timeout(Some(remaining))?;
+        }
+        let mut stream = self.stream;
+        stream.read(buf)
+    }
+}
+
+/// The deadline for a read that has to finish within the timeout, a timeout of zero has no deadline
+fn deadline(timeout: Duration) -> Option<Instant> {
+    if timeout.is_zero() {
+        return None;
+    }
+    return Some(Instant::now() + timeout);
+}
+
+/// Check if a read failed because its timeout ran out, which is reported as WouldBlock on unix and TimedOut on windows
+fn is_timeout(error: &io::Error) -> bool {
+    error.kind() == ErrorKind::WouldBlock || error.kind() == ErrorKind::TimedOut
+}
+
+/// The status a request that could not be read is answered with
+pub fn error_status(error: &Error) -> u16 {
+    match error {
+        Error::Io(io_error) if is_timeout(io_error) => 408,
+        Error::Limit { status, .. } => *status,
+        _ => 400,
+    }
+}
+
+/// Check if the client wants the connection to stay open after the request
+fn wants_keep_alive(request: &Request) -> bool {
+    if header_has_token(&request.headers, "connection", "close") {
+        return false;
+    }
+    if request.http_version == "1.0" {
+        return header_has_token(&request.headers, "connection", "keep-alive");
+    }
+    return true;
+}
+
+/// Check if a header contains the specified token in its comma separated list of values, ignoring case
+fn header_has_token(headers: &HashMap<String, String>, name: &str, token: &str) -> bool {
+    for (key, value) in headers {
+        if key.eq_ignore_ascii_case(name) {
+            return value
+                .split(',')
+                .any(|item| item.trim().eq_ignore_ascii_case(token));
+        }
+    }
+    return false;
+}
+
+/// Answers a request that could not be read with an error status and closes the connection, the rest of the stream can not be trusted after a malformed request
+pub fn write_error(stream: &TcpStream, status: u16) {
+    let mut response = Response::new(status, Vec::new(), HashMap::new());
+    response.header("connection", "close");
+    write_response(stream, response, "1.1", false);
+}
+
+/// Writes a tcp response to the client in the http version of the request, HEAD responses are written without a body. Returns false if the response could not be written
+fn write_response(
+    mut stream: &TcpStream,
+    response: Response,
+    http_version: &str,
+    head: bool,
+) -> bool {
+    match response.write_to(&mut stream, http_version, head) {
+        Ok(_) => return true,
+        Err(e) => {
+            println!("Failed sending response: {}", e);
+            return false;
+        }
+    }
+}
@@ -0,0 +1,114 @@
+//! Embedding of static files into the binary at compile time.
+//!
+//! Call generate from the build script of your crate, with spot as a build dependency:
+//!
+//! ```ignore
+//! // build.rs
+//! fn main() {
+//!     spot::embed::generate("public").unwrap();
+//! }
+//! ```
+//!
+//! and include the generated file list where the server is set up:
+//!
+//! ```ignore
+//! static ASSETS: &[(&str, &[u8])] = spot::include_assets!("public");
+//! app.embedded(ASSETS);
+//! ```
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+/// Includes the files of a directory that was embedded with embed::generate, as pairs of paths relative to the directory and their contents. The directory has to be written exactly as in the build script
+#[macro_export]
+macro_rules! include_assets {
+    ($dir:literal) => {
+        include!(concat!(env!("OUT_DIR"), "/spot_assets/", $dir, ".rs"))
+    };
+}
+
+/// Writes a list of the files in the directory, relative to the crate root, to OUT_DIR so that include_assets! can embed them. Has to be called from a build script.
+///
+/// Cargo is told to rerun the build script when the directory changes, so edited files are embedded on the next build
+pub fn generate(dir: &str) -> io::Result<()> {
+    let out_dir = match env::var_os("OUT_DIR") {
+        Some(out_dir) => PathBuf::from(out_dir),
+        None => {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "OUT_DIR is not set, embed::generate has to be called from a build script",
+            ))
+        }
+    };
+    // The directory name is used as the path of the generated file, so it has to stay inside OUT_DIR
+    let relative = Path::new(dir);
+    if relative
+        .components()
+        .any(|component| !matches!(component, Component::Normal(_) | Component::CurDir))
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} has to be a relative path without ..", dir),
+        ));
+    }
+    let root = match env::var_os("CARGO_MANIFEST_DIR") {
+        Some(manifest_dir) => PathBuf::from(manifest_dir).join(relative),
+        None => relative.to_path_buf(),
+    };
+    let root = root.canonicalize()?;
+
+    let mut files = Vec::new();
+    collect_files(&root, "", &mut files)?;
+    files.sort();
+
+    let mut generated = String::from("&[\n");
+    for (name, path) in files {
+        generated.push_str(&format!(
+            "    ({:?}, include_bytes!({:?}) as &[u8]),\n",
+            name,
+            path.display().to_string()
+        ));
+    }
+    generated.push_str("]\n");
+
+    let output = out_dir.join("spot_assets").join(format!("{}.rs", dir));
+    if let Some(parent) = output.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(&output, generated)?;
+    // Cargo scans the whole directory for changes
+    println!("cargo:rerun-if-changed={}", root.display());
+    Ok(())
+}
+
+/// Adds every file below the directory to files as (path relative to the embedded root, absolute path). Files with names that are not valid utf-8 are skipped
+fn collect_files(
+    directory: &Path,
+    prefix: &str,
+    files: &mut Vec<(String, PathBuf)>,
+) -> io::Result<()> {
+    for entry in fs::read_dir(directory)? {
+        let entry = entry?;
+        let name = match entry.file_name().into_string() {
+            Ok(name) => name,
+            Err(name) => {
+                println!(
+                    "cargo:warning=Skipping embedded file with invalid name {:?}",
+                    name
+                );
+                continue;
+            }
+        };
+        let path = entry.path();
+        let relative = format!("{}{}", prefix, name);
+        // Symbolic links are followed, so linked files are embedded with the contents they have when building
+        let metadata = fs::metadata(&path)?;
+        if metadata.is_dir() {
+            collect_files(&path, &format!("{}/", relative), files)?;
+        } else if metadata.is_file() {
+            files.push((relative, path));
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,44 @@
+use std::fmt;
+use std::io;
+
+/// The errors returned by Spot
+#[derive(Debug)]
+pub enum Error {
+    /// The server could not bind to the address, for example because it is already in use
+    Bind(io::Error),
+    /// Reading from or writing to a connection failed
+    Io(io::Error),
+    /// The server was configured with settings it can not run with, such as zero worker threads
+    Config(String),
+    /// A request did not follow the http syntax
+    Parse(String),
+    /// A request was larger than one of the configured limits, status is the status it is answered with
+    Limit { status: u16, message: String },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Bind(error) => write!(f, "Failed to bind to ip: {}", error),
+            Error::Io(error) => write!(f, "{}", error),
+            Error::Config(message) => write!(f, "Invalid configuration: {}", message),
+            Error::Parse(message) => write!(f, "{}", message),
+            Error::Limit { message, .. } => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Bind(error) | Error::Io(error) => Some(error),
+            Error::Config(_) | Error::Parse(_) | Error::Limit { .. } => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Error {
+        Error::Io(error)
+    }
+}
@@ -0,0 +1,609 @@
+use crate::app::App;
+use crate::config::{Backpressure, Config};
+use crate::connection::{error_status, reject_connection, respond, write_error};
+use crate::error::Error;
+use crate::http_parser::{BodyReader, HttpParser};
+use crate::request::Request;
+use crate::threadpool::{Task, ThreadPool};
+use std::collections::{HashMap, VecDeque};
+use std::fs::File;
+use std::io::{self, BufRead, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::os::raw::{c_int, c_uint};
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc};
+use std::time::{Duration, Instant};
+
+const EPOLL_CLOEXEC: c_int = 0o2000000;
+const EPOLL_CTL_ADD: c_int = 1;
+const EPOLL_CTL_DEL: c_int = 2;
+const EPOLLIN: u32 = 0x1;
+const EPOLLRDHUP: u32 = 0x2000;
+const EFD_CLOEXEC: c_int = 0o2000000;
+const EFD_NONBLOCK: c_int = 0o4000;
+
+/// How long the event loop waits for events at most, in milliseconds, so that timeouts and the shutdown flag are checked regularly
+const TICK_MILLIS: c_int = 100;
+
+/// The most events handled per wait
+const MAX_EVENTS: usize = 256;
+
+/// How many bytes are read from a connection per event, level triggered epoll reports the connection again if there is more
+const READ_SIZE: usize = 16 * 1024;
+
+/// The token of events for the listener, connections use their file descriptor as token
+const LISTENER: u64 = u64::MAX;
+
+/// The token of events for the waker
+const WAKER: u64 = u64::MAX - 1;
+
+/// The write timeout of responses, the same as for connections handled by a worker from start to end
+const WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The layout of struct epoll_event, which is packed on x86_64
+#[repr(C)]
+#[cfg_attr(target_arch = "x86_64", repr(packed))]
+#[derive(Clone, Copy)]
+struct EpollEvent {
+    events: u32,
+    data: u64,
+}
+
+extern "C" {
+    fn epoll_create1(flags: c_int) -> c_int;
+    fn epoll_ctl(epfd: c_int, op: c_int, fd: c_int, event: *mut EpollEvent) -> c_int;
+    fn epoll_wait(epfd: c_int, events: *mut EpollEvent, maxevents: c_int, timeout: c_int) -> c_int;
+    fn eventfd(initval: c_uint, flags: c_int) -> c_int;
+}
+
+/// Turns the return value of a system call into an io error if it failed
+fn check(result: c_int) -> io::Result<c_int> {
+    if result < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    return Ok(result);
+}
+
+/// An epoll instance, closed when dropped
+struct Epoll {
+    fd: OwnedFd,
+}
+
+impl Epoll {
+    fn new() -> io::Result<Epoll> {
+        let fd = check(unsafe { epoll_create1(EPOLL_CLOEXEC) })?;
+        return Ok(Epoll {
+            fd: unsafe { OwnedFd::from_raw_fd(fd) },
+        });
+    }
+
+    /// Watches the file descriptor until it is removed or closed, events for it carry the token
+    fn add(&self, fd: RawFd, token: u64) -> io::Result<()> {
+        let mut event = EpollEvent {
+            events: EPOLLIN | EPOLLRDHUP,
+            data: token,
+        };
+        check(unsafe { epoll_ctl(self.fd.as_raw_fd(), EPOLL_CTL_ADD, fd, &mut event) })?;
+        return Ok(());
+    }
+
+    fn remove(&self, fd: RawFd) -> io::Result<()> {
+        let mut event = EpollEvent { events: 0, data: 0 };
+        check(unsafe { epoll_ctl(self.fd.as_raw_fd(), EPOLL_CTL_DEL, fd, &mut event) })?;
+        return Ok(());
+    }
+
+    /// Waits for events and returns how many were written to the start of events
+    fn wait(&self, events: &mut [EpollEvent]) -> io::Result<usize> {
+        let count = unsafe {
+            epoll_wait(
+                self.fd.as_raw_fd(),
+                events.as_mut_ptr(),
+                events.len() as c_int,
+                TICK_MILLIS,
+            )
+        };
+        match check(count) {
+            Ok(count) => return Ok(count as usize),
+            // A signal such as SIGINT interrupts the wait, the loop then checks if it should shut down
+            Err(error) if error.kind() == ErrorKind::Interrupted => return Ok(0),
+            Err(error) => return Err(error),
+        }
+    }
+}
+
+/// Wakes the event loop from a worker thread, through an eventfd that the loop watches
+struct Waker {
+    file: File,
+}
+
+impl Waker {
+    fn new() -> io::Result<Waker> {
+        let fd = check(unsafe { eventfd(0, EFD_CLOEXEC | EFD_NONBLOCK) })?;
+        return Ok(Waker {
+            file: unsafe { File::from_raw_fd(fd) },
+        });
+    }
+
+    fn wake(&self) {
+        // The write only fails if the counter is about to overflow, the loop is woken up anyway then
+        let _result = (&self.file).write(&1u64.to_ne_bytes());
+    }
+
+    /// Resets the counter so that the eventfd stops being readable
+    fn reset(&self) {
+        let mut counter = [0; 8];
+        let _result = (&self.file).read(&mut counter);
+    }
+}
+
+/// A connection that is waiting for, or reading, its next request
+struct Conn {
+    stream: TcpStream,
+    // Bytes that have been received but not parsed yet
+    buffer: Vec<u8>,
+    // How much of the buffer has been searched for line breaks in the request head
+    head_scanned: usize,
+    // Where the line of the request head that is being received starts in the buffer
+    line_start: usize,
+    // The request whose head has been parsed, with the reader for the rest of its body
+    reading: Option<(Request, BodyReader)>,
+    first_request: bool,
+    // When the connection started waiting for its next request
+    idle_since: Instant,
+    // When the first byte of the current request arrived
+    request_started: Option<Instant>,
+    // When the request line and headers of the current request had arrived
+    headers_read: Option<Instant>,
+}
+
+impl Conn {
+    fn new(stream: TcpStream) -> Conn {
+        return Conn {
+            stream,
+            buffer: Vec::new(),
+            head_scanned: 0,
+            line_start: 0,
+            reading: None,
+            first_request: true,
+            idle_since: Instant::now(),
+            request_started: None,
+            headers_read: None,
+        };
+    }
+
+    fn token(&self) -> u64 {
+        self.stream.as_raw_fd() as u64
+    }
+
+    /// Continues reading the request from the received bytes, returns the request once it has been read in full. The head is parsed once it has arrived and the body is read as it arrives, so the parsed bytes are dropped from the buffer and never parsed twice
+    fn read_request(&mut self, app: &App) -> Result<Option<Request>, Error> {
+        let config = &app.config;
+        if self.reading.is_none() {
+            if !self.head_received(config) {
+                return Ok(None);
+            }
+            let mut received = Received {
+                data: &self.buffer,
+                position: 0,
+            };
+            let request = match HttpParser::parse(&mut received, config) {
+                Ok(request) => request,
+                Err(Error::Io(error)) if error.kind() == ErrorKind::WouldBlock => return Ok(None),
+                Err(error) => return Err(error),
+            };
+            let consumed = received.position;
+            self.buffer.drain(..consumed);
+            self.head_scanned = 0;
+            self.line_start = 0;
+            self.headers_read = Some(Instant::now());
+            let body_reader = BodyReader::new(&request, app.body_limit(&request))?;
+            self.reading = Some((request, body_reader));
+        }
+        let (request, body_reader) = match &mut self.reading {
+            Some(reading) => reading,
+            None => return Ok(None),
+        };
+        let mut received = Received {
+            data: &self.buffer,
+            position: 0,
+        };
+        // A step that runs out of bytes in the middle of a line is repeated from the start of the line once more bytes arrive
+        let mut consumed = 0;
+        let complete = loop {
+            match body_reader.step(&mut received, request, config) {
+                Ok(complete) => {
+                    consumed = received.position;
+                    if complete {
+                        break true;
+                    }
+                }
+                Err(Error::Io(error)) if error.kind() == ErrorKind::WouldBlock => break false,
+                Err(error) => return Err(error),
+            }
+        };
+        // Bytes after the request belong to the next request on the connection
+        self.buffer.drain(..consumed);
+        if !complete {
+            return Ok(None);
+        }
+        return Ok(self.reading.take().map(|(request, _body_reader)| request));
+    }
+
+    /// Check if a line of the request head has been completed since the last check, or the line being received is longer than the limits allow. Parsing the head can only end in a different way then, so it is not parsed again for every read of a client that sends a few bytes at a time
+    fn head_received(&mut self, config: &Config) -> bool {
+        let new_bytes = &self.buffer[self.head_scanned..];
+        let line_end = new_bytes.iter().rposition(|byte| *byte == b'\n');
+        if let Some(line_end) = line_end {
+            self.line_start = self.head_scanned + line_end + 1;
+        }
+        self.head_scanned = self.buffer.len();
+        // The first line is the request line, the others are headers
+        let max_line = if self.line_start == 0 {
+            config.max_request_line + 2
+        } else {
+            config.max_header_bytes + 2
+        };
+        return line_end.is_some() || self.buffer.len() - self.line_start > max_line;
+    }
+}
+
+/// Reads the bytes received so far. The end is reported as WouldBlock instead of the end of the stream, so the parser stops with an error when the request is incomplete
+struct Received<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl Read for Received<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let amount = available.len().min(buf.len());
+        buf[..amount].copy_from_slice(&available[..amount]);
+        self.consume(amount);
+        Ok(amount)
+    }
+}
+
+impl BufRead for Received<'_> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.position == self.data.len() {
+            return Err(io::Error::from(ErrorKind::WouldBlock));
+        }
+        Ok(&self.data[self.position..])
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.position = (self.position + amount).min(self.data.len());
+    }
+}
+
+/// Counts a connection as busy in a worker until it is dropped
+struct Busy {
+    count: Arc<AtomicUsize>,
+}
+
+impl Drop for Busy {
+    fn drop(&mut self) {
+        self.count.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// A request read in full by the event loop, answered by a worker which then hands the connection back
+struct ReadRequest {
+    conn: Conn,
+    request: Request,
+    returned: mpsc::Sender<Conn>,
+    waker: Arc<Waker>,
+    _busy: Busy,
+}
+
+impl Task for ReadRequest {
+    fn run(self: Box<Self>, app: &App) {
+        let ReadRequest {
+            conn,
+            request,
+            returned,
+            waker,
+            _busy,
+        } = *self;
+        if let Some(conn) = answer(conn, request, app) {
+            // Sending fails if the event loop has stopped, the connection is closed then
+            let _result = returned.send(conn);
+        }
+        drop(_busy);
+        // The loop takes the connection back, and can hand a parked request to the now free worker
+        waker.wake();
+    }
+
+    fn reject(self: Box<Self>, retry_after: u64) {
+        reject_connection(&self.conn.stream, retry_after);
+    }
+}
+
+/// Answers the request and returns the connection if it is kept alive
+fn answer(mut conn: Conn, request: Request, app: &App) -> Option<Conn> {
+    // The response is written with blocking writes, like the responses of connections handled by a worker from start to end
+    let blocking = conn.stream.set_nonblocking(false).is_ok()
+        && conn.stream.set_write_timeout(Some(WRITE_TIMEOUT)).is_ok();
+    if !blocking || !respond(&conn.stream, request, app) {
+        return None;
+    }
+    if conn.stream.set_nonblocking(true).is_err() {
+        return None;
+    }
+    conn.first_request = false;
+    conn.idle_since = Instant::now();
+    conn.request_started = None;
+    conn.headers_read = None;
+    return Some(conn);
+}
+
+/// Owns every connection that is not being answered by a worker
+struct EventLoop<'a> {
+    epoll: Epoll,
+    waker: Arc<Waker>,
+    listener: &'a TcpListener,
+    // Whether the listener is watched, it is not while the server blocks new connections
+    listening: bool,
+    connections: HashMap<u64, Conn>,
+    // The amount of connections that are being answered by a worker, or are parked waiting for one
+    busy: Arc<AtomicUsize>,
+    // Requests that are waiting for room in the full queue, in the order they were read. Their connections are not read from while they wait
+    parked: VecDeque<Box<dyn Task>>,
+    // Connections come back from the workers through this channel when they are kept alive
+    return_sender: mpsc::Sender<Conn>,
+    returned: mpsc::Receiver<Conn>,
+    // When the connections were last checked for timeouts, they are checked once per tick so that busy loops do not scan every connection after every event
+    last_sweep: Instant,
+    app: Arc<App>,
+}
+
+/// Serves the connections of the listener with an epoll event loop until the server shuts down.
+///
+/// The loop accepts connections and reads requests from all of them without blocking, so idle keep-alive connections do not take up a worker thread. Requests that have been read in full are handed to the thread pool, where the routes and middleware run and the response is written like in the thread per connection model
+pub fn run(listener: &TcpListener, pool: &mut ThreadPool, app: Arc<App>) -> io::Result<()> {
+    let epoll = Epoll::new()?;
+    let waker = Arc::new(Waker::new()?);
+    epoll.add(listener.as_raw_fd(), LISTENER)?;
+    epoll.add(waker.file.as_raw_fd(), WAKER)?;
+    let (return_sender, returned) = mpsc::channel();
+    let mut event_loop = EventLoop {
+        epoll,
+        waker,
+        listener,
+        listening: true,
+        connections: HashMap::new(),
+        busy: Arc::new(AtomicUsize::new(0)),
+        parked: VecDeque::new(),
+        return_sender,
+        returned,
+        last_sweep: Instant::now(),
+        app,
+    };
+
+    let mut events = vec![EpollEvent { events: 0, data: 0 }; MAX_EVENTS];
+    while !event_loop.app.shutdown.is_shutdown() {
+        let count = event_loop.epoll.wait(&mut events)?;
+        for event in &events[..count] {
+            let token = event.data;
+            match token {
+                LISTENER => event_loop.accept(),
+                WAKER => event_loop.waker.reset(),
+                token => event_loop.read(token, pool),
+            }
+        }
+        event_loop.take_returned(pool);
+        event_loop.dispatch_parked(pool);
+        event_loop.close_timed_out();
+        event_loop.update_listening();
+    }
+    // Requests that are still waiting for room in the queue are turned away like the connections a blocked thread pool is waiting to queue. Idle connections are closed when the loop is dropped, the workers finish the requests they have
+    for task in event_loop.parked.drain(..) {
+        task.reject(1);
+    }
+    return Ok(());
+}
+
+impl EventLoop<'_> {
+    /// The amount of open connections, both those owned by the loop and those being answered
+    fn open_connections(&self) -> usize {
+        self.connections.len() + self.busy.load(Ordering::SeqCst)
+    }
+
+    /// Accepts every connection that is waiting in the backlog
+    fn accept(&mut self) {
+        let config = &self.app.config;
+        loop {
+            let full = match config.max_connections {
+                Some(max_connections) => self.open_connections() >= max_connections,
+                None => false,
+            };
+            // Blocked connections wait in the backlog, the listener stops being watched until there is room
+            if full && config.backpressure == Backpressure::Block {
+                return;
+            }
+            let stream = match self.listener.accept() {
+                Ok((stream, _address)) => stream,
+                Err(error) if error.kind() == ErrorKind::WouldBlock => return,
+                Err(error) => {
+                    println!("{}", error);
+                    return;
+                }
+            };
+            if full {
+                if let Backpressure::Reject(retry_after) = config.backpressure {
+                    reject_connection(&stream, retry_after);
+                }
+                continue;
+            }
+            if let Err(error) = stream.set_nonblocking(true) {
+                println!("{}", error);
+                continue;
+            }
+            let conn = Conn::new(stream);
+            if let Err(error) = self.epoll.add(conn.stream.as_raw_fd(), conn.token()) {
+                println!("{}", error);
+                continue;
+            }
+            self.connections.insert(conn.token(), conn);
+        }
+    }
+
+    /// Reads what the client has sent and hands the request to the pool once it is complete
+    fn read(&mut self, token: u64, pool: &mut ThreadPool) {
+        let conn = match self.connections.get_mut(&token) {
+            Some(conn) => conn,
+            None => return,
+        };
+        let mut chunk = [0; READ_SIZE];
+        let closed = match (&conn.stream).read(&mut chunk) {
+            Ok(0) => true,
+            Ok(read) => {
+                conn.buffer.extend_from_slice(&chunk[..read]);
+                false
+            }
+            Err(error) if error.kind() == ErrorKind::WouldBlock => return,
+            Err(error) if error.kind() == ErrorKind::Interrupted => return,
+            Err(_error) => true,
+        };
+        // A client can send a request and close its side of the connection, the request is still answered
+        self.parse(token, pool);
+        if closed {
+            self.close(token);
+        }
+    }
+
+    /// Reads the request from the received bytes of a connection, and hands it to the pool once it is complete. Malformed requests and requests above the limits are answered with an error and closed
+    fn parse(&mut self, token: u64, pool: &mut ThreadPool) {
+        let conn = match self.connections.get_mut(&token) {
+            Some(conn) => conn,
+            None => return,
+        };
+        if conn.buffer.is_empty() {
+            return;
+        }
+        conn.request_started.get_or_insert(Instant::now());
+        let request = match conn.read_request(&self.app) {
+            Ok(Some(request)) => request,
+            Ok(None) => return,
+            Err(error) => {
+                println!("HTTP Parser Error: {}", error);
+                write_error(&conn.stream, error_status(&error));
+                self.close(token);
+                return;
+            }
+        };
+
+        let conn = match self.connections.remove(&token) {
+            Some(conn) => conn,
+            None => return,
+        };
+        if let Err(error) = self.epoll.remove(conn.stream.as_raw_fd()) {
+            println!("{}", error);
+            return;
+        }
+        self.busy.fetch_add(1, Ordering::SeqCst);
+        let task = Box::new(ReadRequest {
+            conn,
+            request,
+            returned: self.return_sender.clone(),
+            waker: Arc::clone(&self.waker),
+            _busy: Busy {
+                count: Arc::clone(&self.busy),
+            },
+        });
+        // Requests are handed over in the order they were read
+        if !self.parked.is_empty() {
+            self.parked.push_back(task);
+            return;
+        }
+        if let Some(task) = pool.execute_task(task) {
+            self.parked.push_back(task);
+        }
+    }
+
+    /// Hands the parked requests to the pool until the queue is full again
+    fn dispatch_parked(&mut self, pool: &mut ThreadPool) {
+        while let Some(task) = self.parked.pop_front() {
+            if let Some(task) = pool.execute_task(task) {
+                self.parked.push_front(task);
+                return;
+            }
+        }
+    }
+
+    /// Watches the connections the workers are done with again
+    fn take_returned(&mut self, pool: &mut ThreadPool) {
+        while let Ok(conn) = self.returned.try_recv() {
+            let token = conn.token();
+            if let Err(error) = self.epoll.add(conn.stream.as_raw_fd(), token) {
+                println!("{}", error);
+                continue;
+            }
+            self.connections.insert(token, conn);
+            // A pipelined request may already have been received, epoll does not report it again
+            self.parse(token, pool);
+        }
+    }
+
+    /// Closes the connections whose idle, keep-alive, header or body timeout has run out. Everything but idle keep-alive connections are answered with 408 Request Timeout
+    ///
+    /// Runs at most once per tick, so timeouts are enforced up to TICK_MILLIS late
+    fn close_timed_out(&mut self) {
+        let config = &self.app.config;
+        let now = Instant::now();
+        if now.duration_since(self.last_sweep) < Duration::from_millis(TICK_MILLIS as u64) {
+            return;
+        }
+        self.last_sweep = now;
+        let mut timed_out = Vec::new();
+        for (token, conn) in &self.connections {
+            let (since, timeout, answer) = match (conn.request_started, conn.headers_read) {
+                (None, _) if conn.first_request => (conn.idle_since, config.idle_timeout, true),
+                (None, _) => (conn.idle_since, config.keep_alive_timeout, false),
+                (Some(started), None) => (started, config.header_timeout, true),
+                (Some(_), Some(headers_read)) => (headers_read, config.body_timeout, true),
+            };
+            if !timeout.is_zero() && now.duration_since(since) >= timeout {
+                timed_out.push((*token, answer));
+            }
+        }
+        for (token, answer) in timed_out {
+            if answer {
+                if let Some(conn) = self.connections.get(&token) {
+                    write_error(&conn.stream, 408);
+                }
+            }
+            self.close(token);
+        }
+    }
+
+    /// Stops watching the listener while the server is at its connection limit and blocks new connections, and starts again once there is room
+    fn update_listening(&mut self) {
+        let config = &self.app.config;
+        let full = match config.max_connections {
+            Some(max_connections) => {
+                config.backpressure == Backpressure::Block
+                    && self.open_connections() >= max_connections
+            }
+            None => false,
+        };
+        let result = if full && self.listening {
+            self.epoll.remove(self.listener.as_raw_fd())
+        } else if !full && !self.listening {
+            self.epoll.add(self.listener.as_raw_fd(), LISTENER)
+        } else {
+            return;
+        };
+        match result {
+            Ok(_) => self.listening = !full,
+            Err(error) => println!("{}", error),
+        }
+    }
+
+    /// Closes a connection, which also removes it from epoll
+    fn close(&mut self, token: u64) {
+        self.connections.remove(&token);
+    }
+}
@@ -1,32 +1,139 @@
-pub struct FileParser {}
+use std::collections::HashMap;
+
+/// Content types for file endings that are known without any configuration. Text types are served as utf-8
+const BUILT_IN_TYPES: &[(&str, &str)] = &[
+    // Text
+    ("html", "text/html; charset=utf-8"),
+    ("htm", "text/html; charset=utf-8"),
+    ("css", "text/css; charset=utf-8"),
+    ("csv", "text/csv; charset=utf-8"),
+    ("txt", "text/plain; charset=utf-8"),
+    ("md", "text/markdown; charset=utf-8"),
+    ("xml", "text/xml; charset=utf-8"),
+    ("ics", "text/calendar; charset=utf-8"),
+    ("vtt", "text/vtt; charset=utf-8"),
+    // Scripts and data
+    ("js", "text/javascript; charset=utf-8"),
+    ("mjs", "text/javascript; charset=utf-8"),
+    ("cjs", "text/javascript; charset=utf-8"),
+    ("json", "application/json; charset=utf-8"),
+    ("map", "application/json; charset=utf-8"),
+    ("jsonld", "application/ld+json; charset=utf-8"),
+    ("webmanifest", "application/manifest+json; charset=utf-8"),
+    ("wasm", "application/wasm"),
+    ("rss", "application/rss+xml; charset=utf-8"),
+    ("atom", "application/atom+xml; charset=utf-8"),
+    ("xhtml", "application/xhtml+xml; charset=utf-8"),
+    // Images
+    ("ico", "image/x-icon"),
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("png", "image/png"),
+    ("apng", "image/apng"),
+    ("gif", "image/gif"),
+    ("svg", "image/svg+xml; charset=utf-8"),
+    ("webp", "image/webp"),
+    ("avif", "image/avif"),
+    ("bmp", "image/bmp"),
+    ("tif", "image/tiff"),
+    ("tiff", "image/tiff"),
+    // Fonts
+    ("woff", "font/woff"),
+    ("woff2", "font/woff2"),
+    ("ttf", "font/ttf"),
+    ("otf", "font/otf"),
+    ("eot", "application/vnd.ms-fontobject"),
+    // Audio
+    ("mp3", "audio/mpeg"),
+    ("wav", "audio/wav"),
+    ("ogg", "audio/ogg"),
+    ("oga", "audio/ogg"),
+    ("opus", "audio/opus"),
+    ("flac", "audio/flac"),
+    ("aac", "audio/aac"),
+    ("m4a", "audio/mp4"),
+    ("weba", "audio/webm"),
+    ("mid", "audio/midi"),
+    ("midi", "audio/midi"),
+    // Video
+    ("mp4", "video/mp4"),
+    ("m4v", "video/mp4"),
+    ("webm", "video/webm"),
+    ("ogv", "video/ogg"),
+    ("mov", "video/quicktime"),
+    ("avi", "video/x-msvideo"),
+    ("mpeg", "video/mpeg"),
+    ("ts", "video/mp2t"),
+    // Documents
+    ("pdf", "application/pdf"),
+    ("rtf", "application/rtf"),
+    ("doc", "application/msword"),
+    (
+        "docx",
+        "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+    ),
+    ("xls", "application/vnd.ms-excel"),
+    (
+        "xlsx",
+        "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+    ),
+    ("ppt", "application/vnd.ms-powerpoint"),
+    (
+        "pptx",
+        "application/vnd.openxmlformats-officedocument.presentationml.presentation",
+    ),
+    ("odt", "application/vnd.oasis.opendocument.text"),
+    ("epub", "application/epub+zip"),
+    // Archives
+    ("zip", "application/zip"),
+    ("gz", "application/gzip"),
+    ("tar", "application/x-tar"),
+    ("bz2", "application/x-bzip2"),
+    ("xz", "application/x-xz"),
+    ("7z", "application/x-7z-compressed"),
+    ("rar", "application/vnd.rar"),
+    ("br", "application/x-brotli"),
+];
+
+/// The content type of files with unknown endings
+const DEFAULT_TYPE: &str = "application/octet-stream";
+
+/// Maps file endings to content types, using the built in types unless they are overridden
+#[derive(Default)]
+pub struct FileParser {
+    // Content types registered with Spot::mime_type, keyed by lowercase file ending
+    custom_types: HashMap<String, String>,
+}
 
 impl FileParser {
-    /// takes inn a file ending and returns the appropriate content-type header definition
+    /// Creates a parser that only knows the built in types
+    pub fn new() -> FileParser {
+        return FileParser {
+            custom_types: HashMap::new(),
+        };
+    }
+
+    /// Registers the content type for a file ending, replacing the built in type if there is one
+    pub fn add_type(&mut self, file_ending: &str, content_type: &str) {
+        self.custom_types.insert(
+            file_ending.trim_start_matches('.').to_lowercase(),
+            String::from(content_type),
+        );
+    }
+
+    /// takes inn a file ending and returns the appropriate content-type header definition. The file ending is matched ignoring case, unknown endings are application/octet-stream
     ///
-    /// For example "html" -> "text/html"
-    pub fn get_type(file_ending: &str) -> String {
-        let supported_file_types = [
-            ("html", "text/html"),
-            ("css", "text/css"),
-            ("json", "application/json"),
-            ("js", "application/javascript"),
-            ("zip", "application/zip"),
-            ("csv", "text/csv"),
-            ("xml", "text/xml"),
-            ("ico", "image/x-icon"),
-            ("jpg", "image/jpeg"),
-            ("jpeg", "image/jpeg"),
-            ("png", "image/png"),
-            ("gif", "image/gif"),
-            ("mp3", "audio/mpeg"),
-            ("mp4", "video/mp4"),
-            ("webm", "video/webm"),
-        ];
-        for supported_type in supported_file_types.iter() {
+    /// For example "html" -> "text/html; charset=utf-8"
+    pub fn get_type(&self, file_ending: &str) -> String {
+        let file_ending = file_ending.to_lowercase();
+        if let Some(content_type) = self.custom_types.get(&file_ending) {
+            return content_type.clone();
+        }
+        for supported_type in BUILT_IN_TYPES.iter() {
             if supported_type.0 == file_ending {
                 return String::from(supported_type.1);
             }
         }
-        return String::from("text/plain");
+        return String::from(DEFAULT_TYPE);
     }
 }
@@ -0,0 +1,165 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const DAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// Formats a time as an HTTP date, for example "Sun, 06 Nov 1994 08:49:37 GMT". Times before 1970 are formatted as the unix epoch
+pub fn format(time: SystemTime) -> String {
+    let seconds = match time.duration_since(UNIX_EPOCH) {
+        Ok(duration) => duration.as_secs(),
+        Err(_error) => 0,
+    };
+    let days = seconds / 86400;
+    let seconds_of_day = seconds % 86400;
+    let (year, month, day) = civil_from_days(days as i64);
+    return format!(
+        "{}, {:02} {} {} {:02}:{:02}:{:02} GMT",
+        DAYS[(days % 7) as usize],
+        day,
+        MONTHS[(month - 1) as usize],
+        year,
+        seconds_of_day / 3600,
+        seconds_of_day % 3600 / 60,
+        seconds_of_day % 60
+    );
+}
+
+/// Parses an HTTP date in any of the three formats clients may send: "Sun, 06 Nov 1994 08:49:37 GMT", "Sunday, 06-Nov-94 08:49:37 GMT" or "Sun Nov  6 08:49:37 1994". Returns None if the date is invalid
+pub fn parse(date: &str) -> Option<SystemTime> {
+    let parts: Vec<&str> = date.split_whitespace().collect();
+    let (day, month, year, time) = match parts.len() {
+        // Sun, 06 Nov 1994 08:49:37 GMT
+        6 if parts[5] == "GMT" => (parts[1], parts[2], parts[3], parts[4]),
+        // Sunday, 06-Nov-94 08:49:37 GMT
+        4 if parts[3] == "GMT" => {
+            let date_parts: Vec<&str> = parts[1].split('-').collect();
+            if date_parts.len() != 3 {
+                return None;
+            }
+            (date_parts[0], date_parts[1], date_parts[2], parts[2])
+        }
+        // Sun Nov  6 08:49:37 1994
+        5 => (parts[2], parts[1], parts[4], parts[3]),
+        _ => return None,
+    };
+    let day: i64 = day.parse().ok()?;
+    let month = MONTHS.iter().position(|name| *name == month)? as i64 + 1;
+    let mut year: i64 = year.parse().ok()?;
+    // Two digit years more than 50 years in the future are in the past century
+    if year < 100 {
+        year += if year < 70 { 2000 } else { 1900 };
+    }
+    let time_parts: Vec<&str> = time.split(':').collect();
+    if time_parts.len() != 3 {
+        return None;
+    }
+    let hours: i64 = time_parts[0].parse().ok()?;
+    let minutes: i64 = time_parts[1].parse().ok()?;
+    let seconds: i64 = time_parts[2].parse().ok()?;
+    if !(1..=31).contains(&day)
+        || !(0..=23).contains(&hours)
+        || !(0..=59).contains(&minutes)
+        || !(0..=60).contains(&seconds)
+    {
+        return None;
+    }
+    // Larger years would overflow the seconds since the epoch, no client sends them
+    if !(1601..=9999).contains(&year) {
+        return None;
+    }
+    let days = days_from_civil(year, month, day);
+    let total = days * 86400 + hours * 3600 + minutes * 60 + seconds;
+    if total < 0 {
+        return None;
+    }
+    Some(UNIX_EPOCH + Duration::from_secs(total as u64))
+}
+
+/// Converts a (year, month, day) date in the proleptic Gregorian calendar to days since the unix epoch
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let mp = if month > 2 { month - 3 } else { month + 9 };
+    let day_of_year = (153 * mp + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    return era * 146097 + day_of_era - 719468;
+}
+
+/// Converts days since the unix epoch to a (year, month, day) date in the proleptic Gregorian calendar
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    return (year, month, day);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn formats_dates() {
+        let time = UNIX_EPOCH + Duration::from_secs(784111777);
+        assert_eq!(format(time), "Sun, 06 Nov 1994 08:49:37 GMT");
+        assert_eq!(format(UNIX_EPOCH), "Thu, 01 Jan 1970 00:00:00 GMT");
+    }
+
+    #[test]
+    fn converts_days_to_dates() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(11016), (2000, 2, 29));
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+    }
+
+    #[test]
+    fn parses_all_three_formats() {
+        let expected = Some(UNIX_EPOCH + Duration::from_secs(784111777));
+        assert_eq!(parse("Sun, 06 Nov 1994 08:49:37 GMT"), expected);
+        assert_eq!(parse("Sunday, 06-Nov-94 08:49:37 GMT"), expected);
+        assert_eq!(parse("Sun Nov  6 08:49:37 1994"), expected);
+    }
+
+    #[test]
+    fn rejects_invalid_dates() {
+        for date in [
+            "",
+            "Sun, 06 Nov 1994 08:49:37",
+            "Sun, 06 Foo 1994 08:49:37 GMT",
+            "Sun, 32 Nov 1994 08:49:37 GMT",
+            "Sun, 06 Nov 1994 24:00:00 GMT",
+            "Sun, 06 Nov 1994 08:49 GMT",
+            "Wed, 31 Dec 1969 23:59:59 GMT",
+            "Sun, 06 Nov 99999999999999999 08:49:37 GMT",
+            "Sun Nov  6 08:49:37 9223372036854775807",
+            "Sun, 06 Nov 10000 08:49:37 GMT",
+            "Sun, 06 Nov 1994 -1:49:37 GMT",
+        ] {
+            assert_eq!(parse(date), None, "date {:?}", date);
+        }
+    }
+
+    #[test]
+    fn converts_dates_to_days_and_back() {
+        for days in (-800000..800000).step_by(997) {
+            let (year, month, day) = civil_from_days(days);
+            assert_eq!(days_from_civil(year, month, day), days);
+        }
+    }
+
+    #[test]
+    fn round_trips_formatted_dates() {
+        let time = UNIX_EPOCH + Duration::from_secs(1700000000);
+        assert_eq!(parse(&format(time)), Some(time));
+    }
+}
@@ -1,80 +1,60 @@
+use crate::config::Config;
+use crate::error::Error;
 use crate::request;
 use std::collections::HashMap;
-use std::io::{BufRead, BufReader, Read};
-use std::net::TcpStream;
+use std::io::{BufRead, Read};
 
 pub struct HttpParser {}
 
 impl HttpParser {
-    /// Parses an inncomming http request from a tcp stream, either returns the request object or an error string if the parse fails.
-    pub fn parse(stream: &TcpStream) -> Result<request::Request, String> {
-        let mut reader = BufReader::new(stream);
+    /// Parses the request line and headers of an inncomming http request from a buffered tcp stream, either returns the request object or an error if the parse fails.
+    ///
+    /// The body is not read, read_body has to be called before the next request can be parsed from the reader. Only the bytes of the request are consumed, so the reader can be used again to parse the next request on a persistent connection.
+    ///
+    /// Request lines and headers larger than the limits in the config are rejected with Error::Limit without reading the rest of them
+    pub fn parse<R: BufRead>(reader: &mut R, config: &Config) -> Result<request::Request, Error> {
         // Read first line
-        let mut http_request_line = String::new();
-        let _result = reader.by_ref().read_line(&mut http_request_line);
+        let http_request_line = match read_limited_line(reader, config.max_request_line)? {
+            Some(line) => line,
+            None => {
+                return Err(Error::Limit {
+                    status: 414,
+                    message: String::from("Request line too long"),
+                })
+            }
+        };
         let http_request_line_split: Vec<&str> = http_request_line.split_whitespace().collect();
 
         // Input validation
         if http_request_line_split.len() != 3 {
-            return Err(format!(
+            return Err(Error::Parse(format!(
                 "Request line not correct syntax: {}",
                 http_request_line
-            ));
+            )));
         }
 
-        // Process headers and body
+        // Process headers
         let mut http_request_headers = HashMap::new();
-        for line_result in reader.by_ref().lines() {
-            let line = match line_result {
-                Ok(line_string) => line_string,
-                Err(_error) => String::from("ERROR"),
-            };
-            if line == "" {
-                break;
+        read_headers(reader, &mut http_request_headers, config)?;
+        if http_request_headers.contains_key("transfer-encoding") {
+            // A request with both headers could be interpreted differently by a proxy in front of the server, which enables request smuggling
+            if http_request_headers.contains_key("content-length") {
+                return Err(Error::Parse(String::from(
+                    "Request has both transfer-encoding and content-length headers",
+                )));
             }
-            if line == "ERROR" {
-                return Err(String::from("Failed to read line from TCP stream"));
-            }
-            let mut iter = line.split(": ");
-            let key = match iter.next() {
-                Some(result) => result,
-                None => "Error: no key",
+            // Chunked must be the last encoding, otherwise the length of the body can not be determined
+            let chunked = match http_request_headers["transfer-encoding"].rsplit(',').next() {
+                Some(encoding) => encoding.trim().eq_ignore_ascii_case("chunked"),
+                None => false,
             };
-            if key == "Error: no key" {
-                return Err(String::from("Faulty request syntax, could not parse"));
+            if !chunked {
+                return Err(Error::Parse(String::from("Unsupported transfer-encoding")));
             }
-            let value = match iter.next() {
-                Some(result) => result,
-                None => "Error: no value",
-            };
-            if value == "Error: no value" {
-                return Err(String::from("Faulty request syntax, could not parse"));
+        } else if let Some(content_length) = http_request_headers.get("content-length") {
+            if parse_content_length(content_length).is_none() {
+                return Err(Error::Parse(String::from("Invalid content-legth header")));
             }
-            http_request_headers.insert(key.to_lowercase(), String::from(value));
-        }
-        let body;
-        // If content lenght header is set we assume it has a body and try to read it
-        if http_request_headers.contains_key("content-length") {
-            let body_length: i32 = match http_request_headers["content-length"].parse() {
-                Ok(result) => result,
-                Err(_error) => -1,
-            };
-            if body_length < 0 {
-                return Err(String::from("Invalid content-legth header"));
-            }
-            let mut body_bytes = vec![0; body_length as usize];
-            let result = reader.by_ref().read_exact(&mut body_bytes);
-            let fail = match result {
-                Ok(_result) => false,
-                Err(_error) => true,
-            };
-            if fail {
-                return Err(String::from("Read request body failed"));
-            } else {
-                body = body_bytes;
-            }
-        } else {
-            body = Vec::new();
         }
 
         // Get parameters from request
@@ -105,10 +85,433 @@ impl HttpParser {
         Ok(request::Request::new(
             String::from(http_request_line_split[1]),
             parameters,
-            body,
+            Vec::new(),
             http_version,
             method,
             http_request_headers,
         ))
     }
+
+    /// Reads the body of a request parsed with parse, using the chunked encoding or content-length header of the request to find its end.
+    ///
+    /// Bodies larger than the limit are rejected with Error::Limit, a content-length above the limit is rejected before any of the body is read
+    pub fn read_body<R: BufRead>(
+        reader: &mut R,
+        request: &mut request::Request,
+        limit: usize,
+        config: &Config,
+    ) -> Result<(), Error> {
+        let mut body_reader = BodyReader::new(request, limit)?;
+        while !body_reader.step(reader, request, config)? {}
+        Ok(())
+    }
+}
+
+/// Reads the body of a request in steps, so that reading can continue where it stopped when the reader runs out of data. Used by read_body, and by the event loop which only has the bytes received so far
+pub struct BodyReader {
+    state: BodyState,
+    // The largest body that is accepted
+    limit: usize,
+}
+
+/// The part of the body a body reader reads next
+enum BodyState {
+    // The bytes of a body with a content-length that have not been read yet
+    Length(u64),
+    // The size line of the next chunk
+    ChunkSize,
+    // The bytes of the current chunk that have not been read yet
+    ChunkData(usize),
+    // The line break after a chunk
+    ChunkEnd,
+    // The trailer section after the last chunk
+    Trailers,
+    Done,
+}
+
+impl BodyReader {
+    /// Creates a reader for the body of a request parsed with parse. A content-length above the limit is rejected before any of the body is read
+    pub fn new(request: &request::Request, limit: usize) -> Result<BodyReader, Error> {
+        let state = if request.headers.contains_key("transfer-encoding") {
+            BodyState::ChunkSize
+        } else if let Some(content_length) = request.headers.get("content-length") {
+            let body_length = match parse_content_length(content_length) {
+                Some(body_length) => body_length,
+                None => return Err(Error::Parse(String::from("Invalid content-legth header"))),
+            };
+            if body_length > limit as u64 {
+                return Err(body_too_large());
+            }
+            BodyState::Length(body_length)
+        } else {
+            BodyState::Done
+        };
+        Ok(BodyReader { state, limit })
+    }
+
+    /// Reads the next part of the body into the request and returns true once the body is complete. A part is as much of the body data as the reader has, or a whole chunk size line, chunk line break or trailer section.
+    ///
+    /// Body data is only consumed from the reader once it has been added to the request. If the reader fails in the middle of a line, for example with WouldBlock because it has no more data, the step has to be repeated from the start of the line
+    pub fn step<R: BufRead>(
+        &mut self,
+        reader: &mut R,
+        request: &mut request::Request,
+        config: &Config,
+    ) -> Result<bool, Error> {
+        match self.state {
+            BodyState::Length(0) | BodyState::ChunkData(0) => {}
+            BodyState::Length(remaining) => {
+                // The body grows as it is read, so a client that claims a large body without sending it does not get the memory allocated
+                let read = read_available(reader, &mut request.body, remaining)?;
+                self.state = BodyState::Length(remaining - read as u64);
+            }
+            BodyState::ChunkSize => {
+                let size_line = match read_limited_line(reader, MAX_CHUNK_SIZE_LINE)? {
+                    Some(line) => line,
+                    None => return Err(Error::Parse(String::from("Chunk size line too long"))),
+                };
+                let size = parse_chunk_size(&size_line)?;
+                if size > self.limit - request.body.len() {
+                    return Err(body_too_large());
+                }
+                self.state = if size == 0 {
+                    BodyState::Trailers
+                } else {
+                    BodyState::ChunkData(size)
+                };
+            }
+            BodyState::ChunkData(remaining) => {
+                let read = read_available(reader, &mut request.body, remaining as u64)?;
+                self.state = BodyState::ChunkData(remaining - read);
+            }
+            BodyState::ChunkEnd => {
+                // Every chunk is followed by a line break
+                match read_limited_line(reader, 0)? {
+                    Some(_) => self.state = BodyState::ChunkSize,
+                    None => {
+                        return Err(Error::Parse(String::from(
+                            "Chunk not terminated by a line break",
+                        )))
+                    }
+                }
+            }
+            BodyState::Trailers => {
+                let mut trailers = HashMap::new();
+                read_headers(reader, &mut trailers, config)?;
+                for (key, value) in trailers {
+                    // Trailers are not allowed to change how the message is framed
+                    if key != "content-length" && key != "transfer-encoding" && key != "host" {
+                        request.headers.insert(key, value);
+                    }
+                }
+                self.state = BodyState::Done;
+            }
+            BodyState::Done => return Ok(true),
+        }
+        match self.state {
+            BodyState::Length(0) => self.state = BodyState::Done,
+            BodyState::ChunkData(0) => self.state = BodyState::ChunkEnd,
+            _ => {}
+        }
+        Ok(matches!(self.state, BodyState::Done))
+    }
+}
+
+/// Moves at most limit bytes of the data the reader has buffered into the body, reading more if the buffer is empty. Returns how many bytes were moved
+fn read_available<R: BufRead>(
+    reader: &mut R,
+    body: &mut Vec<u8>,
+    limit: u64,
+) -> Result<usize, Error> {
+    let available = reader.fill_buf()?;
+    if available.is_empty() {
+        return Err(Error::Parse(String::from(
+            "Connection closed before the end of the body",
+        )));
+    }
+    let amount = available.len().min(limit.min(usize::MAX as u64) as usize);
+    body.extend_from_slice(&available[..amount]);
+    reader.consume(amount);
+    Ok(amount)
+}
+
+/// Parses the size of a chunk from its size line, chunk extensions after ; are ignored
+fn parse_chunk_size(size_line: &str) -> Result<usize, Error> {
+    let size_string = size_line.split(';').next().unwrap_or_default().trim();
+    // from_str_radix also accepts a leading +, which is not part of the chunk size syntax
+    if size_string.is_empty() || !size_string.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return Err(Error::Parse(format!("Invalid chunk size: {}", size_string)));
+    }
+    match usize::from_str_radix(size_string, 16) {
+        Ok(size) => Ok(size),
+        Err(_error) => Err(Error::Parse(format!("Invalid chunk size: {}", size_string))),
+    }
+}
+
+/// Parses a content-length header, which has to consist of digits only. Rust also accepts a leading + which a proxy in front of the server might not
+fn parse_content_length(value: &str) -> Option<u64> {
+    if value.is_empty() || !value.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+    return value.parse().ok();
+}
+
+/// The error for a request body that is larger than the limit of its route
+fn body_too_large() -> Error {
+    return Error::Limit {
+        status: 413,
+        message: String::from("Request body too large"),
+    };
+}
+
+/// Reads a line of at most limit bytes, not counting the line break, and returns it without the line break. Returns None if the line is longer, without reading the rest of it
+fn read_limited_line<R: BufRead>(reader: &mut R, limit: usize) -> Result<Option<String>, Error> {
+    let mut line = String::new();
+    // Room for a \r\n line break after the longest allowed line
+    let max_read = limit as u64 + 2;
+    let read = reader.by_ref().take(max_read).read_line(&mut line)?;
+    if read == 0 {
+        return Err(Error::Parse(String::from(
+            "Connection closed before the end of the request",
+        )));
+    }
+    if !line.ends_with('\n') && read as u64 == max_read {
+        return Ok(None);
+    }
+    if line.ends_with('\n') {
+        line.pop();
+        if line.ends_with('\r') {
+            line.pop();
+        }
+    }
+    if line.len() > limit {
+        return Ok(None);
+    }
+    Ok(Some(line))
+}
+
+/// Reads header lines into the map until the empty line that ends the header section. Header names are made lowercase.
+///
+/// Fails with 431 Request Header Fields Too Large if there are more headers, or more header bytes, than the config allows
+fn read_headers<R: BufRead>(
+    reader: &mut R,
+    headers: &mut HashMap<String, String>,
+    config: &Config,
+) -> Result<(), Error> {
+    let mut header_count = 0;
+    let mut header_bytes = 0;
+    loop {
+        let remaining_bytes = config.max_header_bytes.saturating_sub(header_bytes);
+        let line = match read_limited_line(reader, remaining_bytes)? {
+            Some(line) => line,
+            None => return Err(headers_too_large()),
+        };
+        if line.is_empty() {
+            return Ok(());
+        }
+        header_count += 1;
+        header_bytes += line.len();
+        if header_count > config.max_header_count {
+            return Err(headers_too_large());
+        }
+        let (key, value) = match line.split_once(':') {
+            Some(result) => result,
+            None => {
+                return Err(Error::Parse(String::from(
+                    "Faulty request syntax, could not parse",
+                )))
+            }
+        };
+        if key.is_empty() || key.ends_with(char::is_whitespace) {
+            return Err(Error::Parse(String::from(
+                "Faulty request syntax, could not parse",
+            )));
+        }
+        let key = key.to_lowercase();
+        let value = String::from(value.trim());
+        // Repeated framing headers could be read differently by a proxy in front of the server, which enables request smuggling
+        if let Some(previous) = headers.get(&key) {
+            if key == "transfer-encoding" || (key == "content-length" && *previous != value) {
+                return Err(Error::Parse(format!("Repeated {} header", key)));
+            }
+        }
+        headers.insert(key, value);
+    }
+}
+
+/// The error for header sections that are larger than the limits
+fn headers_too_large() -> Error {
+    return Error::Limit {
+        status: 431,
+        message: String::from("Request headers too large"),
+    };
+}
+
+/// The longest chunk size line that is accepted, the size itself is at most 16 hex digits so the rest can only be chunk extensions
+const MAX_CHUNK_SIZE_LINE: usize = 1024;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parses a complete request including its body
+    fn parse_request(raw: &str) -> Result<request::Request, Error> {
+        let config = Config::default();
+        let mut reader = raw.as_bytes();
+        let mut request = HttpParser::parse(&mut reader, &config)?;
+        HttpParser::read_body(&mut reader, &mut request, config.max_body_size, &config)?;
+        return Ok(request);
+    }
+
+    #[test]
+    fn reads_content_length_body() {
+        let request = parse_request("POST /a HTTP/1.1\r\ncontent-length: 3\r\n\r\nabc").unwrap();
+        assert_eq!(request.body, b"abc");
+    }
+
+    #[test]
+    fn decodes_chunked_body_and_trailers() {
+        let request = parse_request(
+            "POST /a HTTP/1.1\r\ntransfer-encoding: chunked\r\n\r\n3;ext=1\r\nabc\r\nA\r\n0123456789\r\n0\r\nx-trailer: yes\r\ncontent-length: 5\r\n\r\n",
+        )
+        .unwrap();
+        assert_eq!(request.body, b"abc0123456789");
+        assert_eq!(request.headers["x-trailer"], "yes");
+        assert!(!request.headers.contains_key("content-length"));
+    }
+
+    /// The bytes received so far, reporting the end as WouldBlock like a non-blocking socket
+    struct Partial<'a> {
+        data: &'a [u8],
+        position: usize,
+    }
+
+    impl Read for Partial<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let available = self.fill_buf()?;
+            let amount = available.len().min(buf.len());
+            buf[..amount].copy_from_slice(&available[..amount]);
+            self.consume(amount);
+            Ok(amount)
+        }
+    }
+
+    impl BufRead for Partial<'_> {
+        fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
+            if self.position == self.data.len() {
+                return Err(std::io::ErrorKind::WouldBlock.into());
+            }
+            Ok(&self.data[self.position..])
+        }
+
+        fn consume(&mut self, amount: usize) {
+            self.position += amount;
+        }
+    }
+
+    #[test]
+    fn continues_body_as_it_arrives() {
+        let config = Config::default();
+        let raw = "POST /a HTTP/1.1\r\ntransfer-encoding: chunked\r\n\r\n3\r\nabc\r\nA;x=y\r\n0123456789\r\n0\r\nx-trailer: yes\r\n\r\n";
+        let head_length = raw.find("\r\n\r\n").unwrap() + 4;
+        let mut request = HttpParser::parse(&mut raw.as_bytes(), &config).unwrap();
+        let mut body_reader = BodyReader::new(&request, config.max_body_size).unwrap();
+        // Every step starts where the last complete one ended, like the event loop does with its buffer
+        let mut committed = head_length;
+        let mut complete = false;
+        for received in head_length..=raw.len() {
+            let mut partial = Partial {
+                data: &raw.as_bytes()[..received],
+                position: committed,
+            };
+            loop {
+                match body_reader.step(&mut partial, &mut request, &config) {
+                    Ok(done) => {
+                        committed = partial.position;
+                        if done {
+                            complete = true;
+                            break;
+                        }
+                    }
+                    Err(Error::Io(error)) if error.kind() == std::io::ErrorKind::WouldBlock => {
+                        break
+                    }
+                    Err(error) => panic!("{}", error),
+                }
+            }
+        }
+        assert!(complete);
+        assert_eq!(committed, raw.len());
+        assert_eq!(request.body, b"abc0123456789");
+        assert_eq!(request.headers["x-trailer"], "yes");
+    }
+
+    #[test]
+    fn rejects_invalid_chunk_sizes() {
+        for size in ["+3", "-3", "", "3x", "0x3"] {
+            let raw = format!(
+                "POST /a HTTP/1.1\r\ntransfer-encoding: chunked\r\n\r\n{}\r\nabc\r\n0\r\n\r\n",
+                size
+            );
+            assert!(parse_request(&raw).is_err(), "chunk size {:?}", size);
+        }
+    }
+
+    #[test]
+    fn rejects_chunked_body_over_limit() {
+        let config = Config::default();
+        let mut reader =
+            "POST /a HTTP/1.1\r\ntransfer-encoding: chunked\r\n\r\n4\r\nabcd\r\n0\r\n\r\n"
+                .as_bytes();
+        let mut request = HttpParser::parse(&mut reader, &config).unwrap();
+        match HttpParser::read_body(&mut reader, &mut request, 3, &config) {
+            Err(Error::Limit { status, .. }) => assert_eq!(status, 413),
+            _ => panic!("body over the limit was accepted"),
+        }
+    }
+
+    #[test]
+    fn rejects_ambiguous_framing() {
+        assert!(parse_request(
+            "POST /a HTTP/1.1\r\ntransfer-encoding: chunked\r\ncontent-length: 3\r\n\r\n0\r\n\r\n"
+        )
+        .is_err());
+        assert!(parse_request(
+            "POST /a HTTP/1.1\r\ncontent-length: 2\r\ncontent-length: 50\r\n\r\nab"
+        )
+        .is_err());
+        assert!(parse_request(
+            "POST /a HTTP/1.1\r\ntransfer-encoding: chunked\r\ntransfer-encoding: chunked\r\n\r\n0\r\n\r\n"
+        )
+        .is_err());
+        assert!(parse_request("POST /a HTTP/1.1\r\ncontent-length: +2\r\n\r\nab").is_err());
+        assert!(parse_request("POST /a HTTP/1.1\r\ntransfer-encoding: gzip\r\n\r\n").is_err());
+    }
+
+    #[test]
+    fn accepts_repeated_equal_content_length() {
+        let request =
+            parse_request("POST /a HTTP/1.1\r\ncontent-length: 2\r\nContent-Length: 2\r\n\r\nab")
+                .unwrap();
+        assert_eq!(request.body, b"ab");
+    }
+
+    #[test]
+    fn enforces_header_limits() {
+        let config = Config {
+            max_request_line: 16,
+            max_header_count: 2,
+            ..Config::default()
+        };
+        let status = |raw: &str| match HttpParser::parse(&mut raw.as_bytes(), &config) {
+            Err(Error::Limit { status, .. }) => status,
+            _ => 0,
+        };
+        assert_eq!(status("GET /a-very-long-path HTTP/1.1\r\n\r\n"), 414);
+        assert_eq!(
+            status("GET / HTTP/1.1\r\na: 1\r\nb: 2\r\nc: 3\r\n\r\n"),
+            431
+        );
+        assert_eq!(status("GET / HTTP/1.1\r\na: 1\r\nb: 2\r\n\r\n"), 0);
+    }
 }
@@ -1,200 +1,539 @@
+#![allow(clippy::needless_return, clippy::redundant_field_names)]
 use std::collections::HashMap;
 use std::env;
-use std::fs;
-use std::io::prelude::*;
+use std::io::ErrorKind;
 use std::net::TcpListener;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
 
+mod app;
+mod config;
+mod connection;
+pub mod embed;
+mod error;
+#[cfg(target_os = "linux")]
+mod event_loop;
 mod file_parser;
+mod http_date;
 mod http_parser;
+mod panic_report;
+mod range;
 pub mod request;
 pub mod response;
+mod router;
+mod shutdown;
+mod state;
+mod static_files;
 mod threadpool;
+use app::App;
+pub use config::Backpressure;
+use config::Config;
+pub use error::Error;
 use file_parser::FileParser;
+pub use panic_report::HandlerPanic;
 use request::Request;
 use response::Response;
+pub use shutdown::ShutdownHandle;
+use state::State;
+pub use static_files::StaticMount;
 use threadpool::ThreadPool;
 
+/// A route handler, takes in the request and a premade response and returns the response that is written to the client.
+///
+/// Handlers are stored behind an Arc so that they can be shared between the worker threads
+pub(crate) type Route = Arc<dyn Fn(Request, Response) -> Response + Send + Sync>;
+/// A middleware handler, returns the request and response aswell as whether the request should be forwarded
+pub(crate) type Middleware =
+    Arc<dyn Fn(Request, Response) -> (Request, Response, bool) + Send + Sync>;
+/// Called with a description of every panic in a route or middleware function
+pub(crate) type PanicHook = Arc<dyn Fn(&HandlerPanic) + Send + Sync>;
+
 pub struct Spot {
     /// The amount of worker threads used to handle requests
     amount_of_threads: usize,
-    // Contains all the routes for http resources on the server
-    routes: HashMap<String, fn(Request, Response) -> Response>,
+    // Contains all the routes for http resources on the server, keyed by path and then by request method
+    routes: HashMap<String, HashMap<String, Route>>,
     // Contains all the middleware for the servers resources
-    middleware: Vec<(String, fn(Request, Response) -> (Request, Response, bool))>,
+    middleware: Vec<(String, Middleware)>,
+    // Application state shared between all the workers, retrieved by type with req.state()
+    state: State,
+    // Directories whose files are served when no route matches
+    static_mounts: Vec<StaticMount>,
+    // The content types of served files by file ending
+    file_parser: FileParser,
+    // Body size limits of routes that override the default limit, keyed by path
+    body_limits: HashMap<String, usize>,
+    // Server settings that are shared with the workers
+    config: Config,
+    // Reports panics in routes and middleware
+    panic_hook: Option<PanicHook>,
+    // Stops the server when shutdown is called on it or one of its clones
+    shutdown: ShutdownHandle,
+    // Set when bind hands the routes and state to the workers, after that the server can not be bound again
+    bound: bool,
 }
 
+/// How often the server checks if it should shut down while waiting for connections, new connections are accepted as soon as they arrive
+const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
 impl Spot {
     /// Returns a Spot HTTP server instance with worker threads equal to the specified amount.
     ///
-    /// #Panics
-    ///
-    /// panics if amount of threads is 0
+    /// bind returns Error::Config if amount of threads is 0
     pub fn new(amount_of_threads: usize) -> Spot {
         return Spot {
             amount_of_threads: amount_of_threads,
             routes: HashMap::new(),
             middleware: Vec::new(),
+            state: State::new(),
+            static_mounts: Vec::new(),
+            file_parser: FileParser::new(),
+            body_limits: HashMap::new(),
+            config: Config::default(),
+            panic_hook: None,
+            shutdown: ShutdownHandle::new(),
+            bound: false,
         };
     }
 
     /// Add middleware for specified resources.
     ///
     /// The middleware function takes inn a function that returns a modified response and request, aswell as a boolean is true if the request should be forwarded or false if you wish the server to write the current response.
-    pub fn middle(
-        &mut self,
-        path: &str,
-        function: fn(Request, Response) -> (Request, Response, bool),
-    ) {
-        let mut path_string = String::from(path);
+    ///
+    /// Both functions and closures are accepted, closures can capture state such as a database pool as long as it is Send and Sync.
+    pub fn middle<F>(&mut self, path: &str, function: F)
+    where
+        F: Fn(Request, Response) -> (Request, Response, bool) + Send + Sync + 'static,
+    {
         // Remove trailing / so that pathing is agnostic towards /example/ or /example
-        match path_string.pop() {
-            Some(last_char) => {
-                if last_char != '/' || path_string.len() == 0 {
-                    path_string.push(last_char)
-                }
-            }
-            None => {
-                path_string.push('/');
-            }
-        };
-        self.middleware.push((path_string, function));
+        let path_string = router::normalize_path(path);
+        self.middleware.push((path_string, Arc::new(function)));
+    }
+
+    /// Set how long an idle keep-alive connection is kept open while waiting for the next request. The default is 5 seconds
+    ///
+    /// A timeout of zero disables keep-alive, the connection is then closed after every response
+    pub fn keep_alive_timeout(&mut self, timeout: Duration) {
+        self.config.keep_alive_timeout = timeout;
+    }
+
+    /// Set how long a new connection can stay silent before it is answered with 408 Request Timeout and closed. The default is 10 seconds, zero waits forever
+    pub fn idle_timeout(&mut self, timeout: Duration) {
+        self.config.idle_timeout = timeout;
+    }
+
+    /// Set how long a client has to send the request line and headers of a request, counted from the first byte. Requests that are too slow are answered with 408 Request Timeout. The default is 10 seconds, zero waits forever
+    pub fn header_timeout(&mut self, timeout: Duration) {
+        self.config.header_timeout = timeout;
+    }
+
+    /// Set how long a client has to send the body of a request after the headers. Requests that are too slow are answered with 408 Request Timeout. The default is 30 seconds, zero waits forever
+    pub fn body_timeout(&mut self, timeout: Duration) {
+        self.config.body_timeout = timeout;
+    }
+
+    /// Set the longest request line, the method, url and http version, that is accepted. Longer request lines are answered with 414 URI Too Long. The default is 8 KiB
+    pub fn max_request_line(&mut self, bytes: usize) {
+        self.config.max_request_line = bytes;
+    }
+
+    /// Set how many headers a request can have. Requests with more are answered with 431 Request Header Fields Too Large. The default is 100
+    pub fn max_header_count(&mut self, count: usize) {
+        self.config.max_header_count = count;
+    }
+
+    /// Set how many bytes the header lines of a request can take up together. Requests with more are answered with 431 Request Header Fields Too Large. The default is 16 KiB
+    pub fn max_header_bytes(&mut self, bytes: usize) {
+        self.config.max_header_bytes = bytes;
+    }
+
+    /// Set the largest request body that is accepted. Larger bodies are answered with 413 Content Too Large, a content-length above the limit is rejected before the body is read. The default is 10 MiB
+    pub fn max_body_size(&mut self, bytes: usize) {
+        self.config.max_body_size = bytes;
+    }
+
+    /// Set the largest request body accepted by a route, overriding max_body_size. The path is matched like a route, so patterns such as /upload/:id can be used
+    pub fn body_limit(&mut self, path: &str, bytes: usize) {
+        self.body_limits.insert(router::normalize_path(path), bytes);
+    }
+
+    /// Set how many accepted connections can wait for a free worker thread. The default is 128, zero only accepts connections when a worker is free
+    pub fn queue_depth(&mut self, depth: usize) {
+        self.config.queue_depth = depth;
+    }
+
+    /// Set the most connections that can be open at the same time, counting both the connections being handled and those waiting for a worker. There is no limit by default
+    pub fn max_connections(&mut self, max_connections: usize) {
+        self.config.max_connections = Some(max_connections);
+    }
+
+    /// Set what happens to new connections when the queue is full or there are too many open connections. The default is Backpressure::Reject(1), which answers with 503 Service Unavailable and retry-after: 1
+    pub fn backpressure(&mut self, backpressure: Backpressure) {
+        self.config.backpressure = backpressure;
+    }
+
+    /// Set a function that is called when a route or middleware function panics, for example to send the panic to an error tracker. The request is answered with 500 Internal Server Error and the worker thread keeps running.
+    ///
+    /// Without a hook the panic is printed along with the method and url of the request
+    pub fn panic_hook<F>(&mut self, hook: F)
+    where
+        F: Fn(&HandlerPanic) + Send + Sync + 'static,
+    {
+        self.panic_hook = Some(Arc::new(hook));
+    }
+
+    /// Read requests with an epoll event loop instead of giving every connection its own worker thread. The routes and middleware still run on the worker threads, but idle keep-alive connections and slow clients no longer take a worker up, so the server can hold thousands of open connections.
+    ///
+    /// Only supported on linux, other platforms keep using a worker thread per connection
+    pub fn event_loop(&mut self, enabled: bool) {
+        if enabled && !cfg!(target_os = "linux") {
+            println!("Warning: The event loop is only supported on linux, using a worker thread per connection");
+        }
+        self.config.event_loop = enabled;
+    }
+
+    /// Returns a handle that stops the server from another thread. Calling shutdown on the handle makes bind stop accepting connections, wait for the requests that are being handled and then return
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    /// Set how long bind waits for requests that are being handled to finish after the server is told to shut down. The default is 30 seconds, workers that are still busy after the timeout are left running
+    pub fn shutdown_timeout(&mut self, timeout: Duration) {
+        self.config.shutdown_timeout = timeout;
+    }
+
+    /// Shut down the server gracefully when the process receives SIGINT, such as from Ctrl+C, or SIGTERM. A second signal ends the process immediately. Only supported on unix
+    pub fn shutdown_on_signals(&mut self, enabled: bool) {
+        self.config.shutdown_on_signals = enabled;
+    }
+
+    /// Add application state that is shared between all the worker threads. The value can be retrieved by its type in routes and middleware with req.state::<T>()
+    ///
+    /// Adding a value of a type that is already stored replaces the previous value. Use types such as Mutex or atomics for state that is modified by requests
+    pub fn state<T: Send + Sync + 'static>(&mut self, value: T) {
+        if self.state.contains::<T>() {
+            println!("Warning: State of the same type added twice, using latest value");
+        }
+        self.state.insert(value);
     }
 
     /// Add a http resource route which takes in the request and a premade respons, then returns a modifed response that is written to the client
-    pub fn route(&mut self, path: &str, function: fn(Request, Response) -> Response) {
-        let mut path_string = String::from(path);
+    ///
+    /// Both functions and closures are accepted, closures can capture state such as a database pool as long as it is Send and Sync.
+    ///
+    /// The route handles every request method, use get, post, put, delete or patch to add a route for a single method.
+    ///
+    /// Segments starting with : are path parameters, for example /user/:id/posts/:post_id. The captured values are avalible in req.path_params
+    pub fn route<F>(&mut self, path: &str, function: F)
+    where
+        F: Fn(Request, Response) -> Response + Send + Sync + 'static,
+    {
+        self.add_route(router::ANY_METHOD, path, function);
+    }
+
+    /// Add a route that only handles GET requests, HEAD requests are also answered by this route but without a body
+    pub fn get<F>(&mut self, path: &str, function: F)
+    where
+        F: Fn(Request, Response) -> Response + Send + Sync + 'static,
+    {
+        self.add_route("GET", path, function);
+    }
+
+    /// Add a route that only handles POST requests
+    pub fn post<F>(&mut self, path: &str, function: F)
+    where
+        F: Fn(Request, Response) -> Response + Send + Sync + 'static,
+    {
+        self.add_route("POST", path, function);
+    }
+
+    /// Add a route that only handles PUT requests
+    pub fn put<F>(&mut self, path: &str, function: F)
+    where
+        F: Fn(Request, Response) -> Response + Send + Sync + 'static,
+    {
+        self.add_route("PUT", path, function);
+    }
+
+    /// Add a route that only handles DELETE requests
+    pub fn delete<F>(&mut self, path: &str, function: F)
+    where
+        F: Fn(Request, Response) -> Response + Send + Sync + 'static,
+    {
+        self.add_route("DELETE", path, function);
+    }
+
+    /// Add a route that only handles PATCH requests
+    pub fn patch<F>(&mut self, path: &str, function: F)
+    where
+        F: Fn(Request, Response) -> Response + Send + Sync + 'static,
+    {
+        self.add_route("PATCH", path, function);
+    }
+
+    /// Add a route for the specified method. Requests to a path that has routes, but none for the request method, are answered with 405 Method Not Allowed
+    fn add_route<F>(&mut self, method: &str, path: &str, function: F)
+    where
+        F: Fn(Request, Response) -> Response + Send + Sync + 'static,
+    {
         // Remove trailing / so that pathing is agnostic towards /example/ or /example
-        match path_string.pop() {
-            Some(last_char) => {
-                if last_char != '/' || path_string.len() == 0 {
-                    path_string.push(last_char)
-                }
-            }
-            None => {
-                path_string.push('/');
-            }
-        };
-        if self.routes.contains_key(&path_string) {
+        let path_string = router::normalize_path(path);
+        let methods = self.routes.entry(path_string).or_default();
+        if methods.contains_key(method) {
             println!(
-                "Warning: Route defined twice ({}), using latest definition",
-                path
+                "Warning: Route defined twice ({} {}), using latest definition",
+                method, path
             );
-            self.routes.remove(&path_string);
         }
-        self.routes.insert(path_string, function);
+        methods.insert(String::from(method), Arc::new(function));
     }
 
-    /// Add a file to routes, it's route is equal to the path where the file lies
+    /// Add a file to routes, it's route is equal to the path where the file lies. Relative paths are resolved against the current directory when the route is added
     pub fn route_file(&mut self, path: &str) {
-        fn function(req: Request, mut res: Response) -> Response {
-            if req.method == "GET" {
-                let path = req.url;
-                let path_split = path.split('.');
-                let file_ending = match path_split.last() {
-                    Some(file_ending) => file_ending,
-                    None => "",
-                };
-                let file_type = FileParser::get_type(file_ending);
-                // remove first / from path and read metadata then file
-                match fs::metadata(&path[1..]) {
-                    Ok(metadata) => {
-                        let mut contents = vec![0; metadata.len() as usize];
-                        match fs::File::open(&path[1..]) {
-                            Ok(mut file) => {
-                                let result = file.read(&mut contents);
-                                match result {
-                                    Ok(_) => {
-                                        res.status(200);
-                                        res.body_bytes(contents);
-                                        res.header("content-type", file_type);
-                                    }
-                                    Err(error) => {
-                                        println!("{}", error);
-                                        res.status(500);
-                                    }
-                                }
-                            }
-                            Err(error) => {
-                                println!("{}", error);
-                                res.status(500);
-                            }
-                        }
-                    }
-                    Err(error) => {
-                        println!("{}", error);
-                        res.status(500);
-                    }
-                }
-            }
-            return res;
-        };
+        let file_path = absolute_path(Path::new(path));
         // Replace Windows specific backslashes in path with forward slashes
         let result = path.replace("\\", "/");
         let route_path = format!("/{}", result);
-        Spot::route(self, &route_path, function);
-    }
-
-    /// Recursive function that adds all the files in the public folder to the server routes
-    fn add_static_files(&mut self, directory: &Path, path: &str) {
-        let dir_iter = fs::read_dir(path).unwrap();
-
-        // Add all files to path hashmap, for each directory in the public folder we run this function recursivly
-        for item in dir_iter {
-            match item {
-                Ok(item_uw) => {
-                    let item_path = item_uw.path().into_os_string().into_string().unwrap();
-                    let item_metadata = item_uw.metadata().unwrap();
-                    if item_metadata.is_dir() {
-                        Spot::add_static_files(self, directory, &item_path);
-                    } else {
-                        Spot::route_file(self, &item_path);
-                    }
-                }
-                Err(error) => {
-                    println!("{}", error);
-                }
-            };
+        Spot::get(
+            self,
+            &route_path,
+            move |req: Request, res: Response| -> Response {
+                static_files::serve_file(&file_path, &req, res)
+            },
+        );
+    }
+
+    /// Make all the files in the specified directory publicly avalible.
+    ///
+    /// Files are looked up when they are requested, so files added after the server started are served aswell. Requests can never reach files outside the directory, paths with .. and symbolic links that point outside the directory are not served
+    pub fn public(&mut self, dir_name: &str) -> &mut StaticMount {
+        self.public_at("/", dir_name)
+    }
+
+    /// Make all the files in the specified directory publicly avalible under a url prefix, for example public_at("/assets", "build") serves build/app.js as /assets/app.js
+    ///
+    /// Several directories can be made public, requests are served from the directory with the longest matching prefix. Relative paths are resolved against the current directory when the directory is added.
+    ///
+    /// Returns the mount so that it can be configured, for example app.public_at("/assets", "build").cache_control("max-age=3600"). If the directory does not exist bind returns Error::Config
+    pub fn public_at(&mut self, prefix: &str, dir_name: &str) -> &mut StaticMount {
+        let directory = absolute_path(Path::new(dir_name));
+        self.add_mount(StaticMount::new(prefix, &directory))
+    }
+
+    /// Serve files embedded in the binary, created with include_assets!, as if they were a public directory. Content types, etags, index.html and the other mount settings work the same as for directories on disk, but embedded directories can not be listed
+    ///
+    /// For example with static ASSETS: &[(&str, &[u8])] = spot::include_assets!("public"); the server no longer needs the public directory when it runs
+    pub fn embedded(
+        &mut self,
+        assets: &'static [(&'static str, &'static [u8])],
+    ) -> &mut StaticMount {
+        self.embedded_at("/", assets)
+    }
+
+    /// Serve files embedded in the binary under a url prefix, see embedded and public_at
+    pub fn embedded_at(
+        &mut self,
+        prefix: &str,
+        assets: &'static [(&'static str, &'static [u8])],
+    ) -> &mut StaticMount {
+        self.add_mount(StaticMount::embedded(prefix, assets))
+    }
+
+    /// Add a static mount, replacing an existing mount with the same prefix
+    fn add_mount(&mut self, mount: StaticMount) -> &mut StaticMount {
+        if self
+            .static_mounts
+            .iter()
+            .any(|existing| existing.prefix() == mount.prefix())
+        {
+            println!(
+                "Warning: Public directory mounted twice at ({}/), using latest directory",
+                mount.prefix()
+            );
+            self.static_mounts
+                .retain(|existing| existing.prefix() != mount.prefix());
         }
+        self.static_mounts.push(mount);
+        let last = self.static_mounts.len() - 1;
+        &mut self.static_mounts[last]
     }
 
-    /// Make all the files in the specified directory publicly avalible
-    pub fn public(&mut self, dir_name: &str) {
-        let path = env::current_dir().unwrap();
-        let new_root_dir = path.join(dir_name);
-        // Set the specified directory as the root when reading files
-        assert!(env::set_current_dir(&new_root_dir).is_ok());
-        let dir = env::current_dir().unwrap();
-        self.add_static_files(dir.as_path(), "");
+    /// Set the content type of served files with the specified file ending, for example mime_type("glb", "model/gltf-binary"). Overrides the built in type if there is one, file endings are matched ignoring case
+    pub fn mime_type(&mut self, file_ending: &str, content_type: &str) {
+        self.file_parser.add_type(file_ending, content_type);
     }
 
-    /// Bind the server to the specified IP address and listen for inncomming http requests
-    pub fn bind(&mut self, ip: &str) -> String {
+    /// Bind the server to the specified IP address and listen for inncomming http requests.
+    ///
+    /// Runs until the server is shut down, then returns Ok. Returns an error if the server can not bind to the address or is configured incorrectly. A server only runs once, binding it again after it has shut down returns Error::Config
+    pub fn bind(&mut self, ip: &str) -> Result<(), Error> {
+        if self.bound {
+            return Err(Error::Config(String::from(
+                "The server has already run, create a new server to bind again",
+            )));
+        }
+        if self.amount_of_threads == 0 {
+            return Err(Error::Config(String::from(
+                "The amount of worker threads has to be at least 1",
+            )));
+        }
+        if let Some(error) = self.static_mounts.iter().find_map(|mount| mount.error()) {
+            return Err(Error::Config(String::from(error)));
+        }
         let listener = match TcpListener::bind(ip) {
             Ok(result) => result,
-            Err(error) => {
-                return String::from(format!("Failed to bind to ip: {}", error));
-            }
+            Err(error) => return Err(Error::Bind(error)),
         };
+        // The listener does not block so that the shutdown flag can be checked between connections
+        listener.set_nonblocking(true)?;
         // Sort middleware by length
-        self.middleware.sort_by(|a, b| a.0.len().cmp(&b.0.len()));
+        self.middleware.sort_by_key(|a| a.0.len());
+        self.bound = true;
 
-        // clone routes and middleware
-        let routes_clone = self.routes.clone();
-        let middleware_clone = self.middleware.clone();
+        // Share routes, middleware and state between the workers
+        let app = App {
+            routes: self.routes.clone(),
+            middleware: self.middleware.clone(),
+            static_mounts: std::mem::take(&mut self.static_mounts),
+            file_parser: Arc::new(std::mem::take(&mut self.file_parser)),
+            state: Arc::new(std::mem::take(&mut self.state)),
+            body_limits: self.body_limits.clone(),
+            config: self.config.clone(),
+            panic_hook: self.panic_hook.clone(),
+            shutdown: self.shutdown.clone(),
+        };
 
         // Create threadpool
-        let pool = ThreadPool::new(self.amount_of_threads, routes_clone, middleware_clone);
+        let app = Arc::new(app);
+        let mut pool = ThreadPool::new(self.amount_of_threads, Arc::clone(&app));
+
+        if self.config.shutdown_on_signals {
+            self.shutdown.listen_for_signals();
+        }
 
         println!("Spot server listening on: http://{}", ip);
-        for stream in listener.incoming() {
-            match stream {
-                Ok(stream_uw) => {
-                    pool.execute(stream_uw);
+        let result = match self.config.event_loop {
+            #[cfg(target_os = "linux")]
+            true => event_loop::run(&listener, &mut pool, app),
+            _ => {
+                self.accept_connections(&listener, &mut pool);
+                Ok(())
+            }
+        };
+
+        // Stop accepting connections before waiting for the workers
+        drop(listener);
+        pool.shutdown(self.config.shutdown_timeout);
+        println!("Shutting down.");
+        result?;
+        return Ok(());
+    }
+
+    /// Hands every accepted connection to the thread pool until the server shuts down, the connection is then handled by a single worker from start to end
+    fn accept_connections(&self, listener: &TcpListener, pool: &mut ThreadPool) {
+        while !self.shutdown.is_shutdown() {
+            match listener.accept() {
+                Ok((stream, _address)) => {
+                    // Accepted streams inherit non-blocking mode from the listener on some platforms
+                    if let Err(error) = stream.set_nonblocking(false) {
+                        println!("{}", error);
+                        continue;
+                    }
+                    pool.execute(stream);
+                }
+                Err(error) if error.kind() == ErrorKind::WouldBlock => {
+                    wait_for_connection(listener, ACCEPT_POLL_INTERVAL);
                 }
                 Err(error) => println!("{}", error),
             }
         }
-        return String::from("Shutting down.");
+    }
+}
+
+/// Joins a relative path onto the current directory, absolute paths are returned as they are
+fn absolute_path(path: &Path) -> PathBuf {
+    if path.is_absolute() {
+        return path.to_path_buf();
+    }
+    match env::current_dir() {
+        Ok(current_dir) => current_dir.join(path),
+        Err(_error) => path.to_path_buf(),
+    }
+}
+
+/// Waits until a connection can be accepted, the timeout runs out or a signal arrives
+#[cfg(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "openbsd",
+    target_os = "netbsd",
+    target_os = "dragonfly"
+))]
+fn wait_for_connection(listener: &TcpListener, timeout: Duration) {
+    use std::os::raw::{c_int, c_short};
+    use std::os::unix::io::AsRawFd;
+
+    const POLLIN: c_short = 1;
+
+    // The type of nfds_t differs between the platforms
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    type NfdsT = std::os::raw::c_ulong;
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    type NfdsT = std::os::raw::c_uint;
+
+    #[repr(C)]
+    struct PollFd {
+        fd: c_int,
+        events: c_short,
+        revents: c_short,
+    }
+
+    extern "C" {
+        fn poll(fds: *mut PollFd, nfds: NfdsT, timeout: c_int) -> c_int;
+    }
+
+    let mut poll_fd = PollFd {
+        fd: listener.as_raw_fd(),
+        events: POLLIN,
+        revents: 0,
+    };
+    // Errors such as EINTR return early, the accept loop then checks the shutdown flag and tries again
+    unsafe {
+        poll(&mut poll_fd, 1, timeout.as_millis() as c_int);
+    }
+}
+
+/// Waits before the next attempt to accept a connection. Poll is only declared for linux, macos and the bsds, so other platforms check for connections every few milliseconds
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "openbsd",
+    target_os = "netbsd",
+    target_os = "dragonfly"
+)))]
+fn wait_for_connection(_listener: &TcpListener, _timeout: Duration) {
+    std::thread::sleep(Duration::from_millis(5));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn refuses_to_bind_twice() {
+        let mut app = Spot::new(1);
+        app.shutdown_handle().shutdown();
+        assert!(app.bind("127.0.0.1:0").is_ok());
+        match app.bind("127.0.0.1:0") {
+            Err(Error::Config(_)) => {}
+            result => panic!("second bind returned {:?}", result),
+        }
     }
 }
@@ -0,0 +1,23 @@
+use std::any::Any;
+
+/// Describes a panic in a route or middleware function, passed to the hook set with Spot::panic_hook
+#[derive(Debug, Clone)]
+pub struct HandlerPanic {
+    /// The method of the request that was being handled
+    pub method: String,
+    /// The url of the request that was being handled, including the query
+    pub url: String,
+    /// The message the handler panicked with
+    pub message: String,
+}
+
+/// Gets the message out of a panic payload, panics with a formatted message carry a String and panics with a literal a &str
+pub(crate) fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        return String::from(*message);
+    }
+    if let Some(message) = payload.downcast_ref::<String>() {
+        return message.clone();
+    }
+    return String::from("Unknown panic");
+}
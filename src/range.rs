@@ -0,0 +1,140 @@
+/// The most ranges answered in a single request, requests for more are answered with the whole file
+const MAX_RANGES: usize = 32;
+
+/// The result of evaluating a range header against the length of a resource
+#[derive(Debug, PartialEq)]
+pub enum Ranges {
+    /// The header is missing, has another unit than bytes or is malformed, so the whole resource is sent
+    Full,
+    /// The inclusive byte ranges that should be sent, sorted and without overlaps
+    Partial(Vec<(u64, u64)>),
+    /// None of the requested ranges lie inside the resource
+    Unsatisfiable,
+}
+
+/// Parses a range header such as "bytes=0-499, -500" for a resource of the specified length
+pub fn parse(header: &str, length: u64) -> Ranges {
+    let specs = match header.trim().strip_prefix("bytes=") {
+        Some(specs) => specs,
+        None => return Ranges::Full,
+    };
+    let mut ranges: Vec<(u64, u64)> = Vec::new();
+    for spec in specs.split(',') {
+        // A header without ranges or with empty list items is malformed, malformed headers are ignored
+        let spec = spec.trim();
+        if spec.is_empty() {
+            return Ranges::Full;
+        }
+        let (start, end) = match spec.split_once('-') {
+            Some(result) => result,
+            None => return Ranges::Full,
+        };
+        let (start, end) = (start.trim(), end.trim());
+        if start.is_empty() {
+            // -500 is the last 500 bytes
+            let suffix: u64 = match end.parse() {
+                Ok(suffix) => suffix,
+                Err(_error) => return Ranges::Full,
+            };
+            if suffix > 0 && length > 0 {
+                ranges.push((length.saturating_sub(suffix), length - 1));
+            }
+            continue;
+        }
+        let start: u64 = match start.parse() {
+            Ok(start) => start,
+            Err(_error) => return Ranges::Full,
+        };
+        let end: u64 = if end.is_empty() {
+            u64::MAX
+        } else {
+            match end.parse() {
+                Ok(end) => end,
+                Err(_error) => return Ranges::Full,
+            }
+        };
+        if end < start {
+            return Ranges::Full;
+        }
+        // Ranges that start after the end of the resource can not be satisfied, ranges that end after it are shortened
+        if start < length {
+            ranges.push((start, end.min(length - 1)));
+        }
+    }
+    if ranges.is_empty() {
+        return Ranges::Unsatisfiable;
+    }
+    // Overlapping and adjacent ranges are merged so that no byte is sent twice
+    ranges.sort();
+    let mut merged: Vec<(u64, u64)> = Vec::with_capacity(ranges.len());
+    for (start, end) in ranges {
+        match merged.last_mut() {
+            Some(last) if start <= last.1.saturating_add(1) => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+    if merged.len() > MAX_RANGES {
+        return Ranges::Full;
+    }
+    Ranges::Partial(merged)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_single_ranges() {
+        assert_eq!(parse("bytes=0-499", 1000), Ranges::Partial(vec![(0, 499)]));
+        assert_eq!(parse("bytes=500-", 1000), Ranges::Partial(vec![(500, 999)]));
+        assert_eq!(parse("bytes=-200", 1000), Ranges::Partial(vec![(800, 999)]));
+        assert_eq!(parse("bytes=-2000", 1000), Ranges::Partial(vec![(0, 999)]));
+        assert_eq!(
+            parse("bytes=900-5000", 1000),
+            Ranges::Partial(vec![(900, 999)])
+        );
+    }
+
+    #[test]
+    fn merges_overlapping_ranges() {
+        assert_eq!(
+            parse("bytes=500-600, 0-99, 550-700, 100-199", 1000),
+            Ranges::Partial(vec![(0, 199), (500, 700)])
+        );
+    }
+
+    #[test]
+    fn ignores_malformed_headers() {
+        for header in [
+            "bytes=",
+            "bytes= ",
+            "bytes=0-1,,",
+            "bytes=,0-1",
+            "bytes=abc",
+            "bytes=5-1",
+            "bytes=1",
+            "items=0-1",
+            "bytes=--1",
+        ] {
+            assert_eq!(parse(header, 1000), Ranges::Full, "header {:?}", header);
+        }
+    }
+
+    #[test]
+    fn rejects_ranges_outside_the_resource() {
+        assert_eq!(parse("bytes=1000-", 1000), Ranges::Unsatisfiable);
+        assert_eq!(parse("bytes=-0", 1000), Ranges::Unsatisfiable);
+        assert_eq!(parse("bytes=0-", 0), Ranges::Unsatisfiable);
+    }
+
+    #[test]
+    fn answers_too_many_ranges_with_the_full_resource() {
+        let specs: Vec<String> = (0..=MAX_RANGES)
+            .map(|i| format!("{}-{}", i * 10, i * 10))
+            .collect();
+        assert_eq!(
+            parse(&format!("bytes={}", specs.join(",")), 10000),
+            Ranges::Full
+        );
+    }
+}
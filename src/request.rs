@@ -1,4 +1,7 @@
+use crate::file_parser::FileParser;
+use crate::state::State;
 use std::collections::HashMap;
+use std::sync::Arc;
 pub struct Request {
     /// The url of the requested resource
     pub url: String,
@@ -6,9 +9,13 @@ pub struct Request {
     ///
     /// for example /user?name=cory&age=21 would yield name and age as keys with cory and 21 as values respectively
     pub params: HashMap<String, String>,
-    /// The body of the request if the request has specified a content-length header, otherwise the string is a fresh Vec::new()
+    /// Contains the path parameters captured by the matched route
+    ///
+    /// for example the route /user/:id requested as /user/12 would yield id as key with 12 as value
+    pub path_params: HashMap<String, String>,
+    /// The body of the request if the request has specified a content-length header or was sent with transfer-encoding: chunked, otherwise the body is a fresh Vec::new()
     pub body: Vec<u8>,
-    /// The http version. Note: Spot only supporst 1.1 at the moment
+    /// The http version, for example 1.1 or 1.0
     pub http_version: String,
     /// The request method (GET, POST, PUT etc). Method should always be fully capitalized.
     pub method: String,
@@ -16,6 +23,10 @@ pub struct Request {
     ///
     /// content-length: 120 would for example yield content-length as a key with value "120"
     pub headers: HashMap<String, String>,
+    // The application state added with Spot::state, shared between all requests
+    pub(crate) state: Arc<State>,
+    // The content types used when serving files, including the ones added with Spot::mime_type
+    pub(crate) file_parser: Arc<FileParser>,
 }
 
 impl Request {
@@ -31,12 +42,20 @@ impl Request {
         return Request {
             url: url,
             params: params,
+            path_params: HashMap::new(),
             body: body,
             http_version: http_version,
             method: method,
             headers: headers,
+            state: Arc::new(State::new()),
+            file_parser: Arc::new(FileParser::new()),
         };
     }
+    /// Returns the application state of the specified type, or None if no state of that type was added with Spot::state
+    pub fn state<T: Send + Sync + 'static>(&self) -> Option<&T> {
+        self.state.get::<T>()
+    }
+
     /// Check if the http request contains the specified list of parameters. Returns a missing parameter if there is one
     pub fn contains_params(&self, keys: Vec<&str>) -> Option<String> {
         for key in keys {
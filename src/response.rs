@@ -1,12 +1,17 @@
+use crate::http_date;
 use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::time::SystemTime;
 
 pub struct Response {
     /// Status code for the response
     pub status: u16,
     /// The response body. If there is no body this is empty
     pub body: Vec<u8>,
-    /// Contains all the desired response headers. No headers are added automatically except for content-length and content-type when adding a body
+    /// Contains all the desired response headers. No headers are added automatically except for content-length and content-type when adding a body, and date when the response is written
     pub headers: HashMap<String, String>,
+    // A body that is streamed to the client while it is read, replaces body when set
+    stream: Option<Box<dyn Read + Send>>,
 }
 
 impl Response {
@@ -16,6 +21,7 @@ impl Response {
             status: status,
             body: body,
             headers: headers,
+            stream: None,
         };
     }
 
@@ -37,6 +43,7 @@ impl Response {
     /// Adds a body from a String to the request, overwrites previous body, then adds content length and content type "text/plain".
     pub fn body(&mut self, data_ref: impl AsRef<str>) {
         let data = data_ref.as_ref().to_string();
+        self.stream = None;
         self.body = data.as_bytes().to_vec();
         // Sets the content length header so that the client knows how far to read
         self.header(String::from("content-length"), self.body.len().to_string());
@@ -45,20 +52,355 @@ impl Response {
 
     /// Adds a body from a byte array, then sets the content-length header to be equal to the size of the array
     pub fn body_bytes(&mut self, data: Vec<u8>) {
+        self.stream = None;
         self.body = data;
         self.header(String::from("content-length"), self.body.len().to_string());
     }
 
+    /// Adds a body that is streamed to the client as it is read, so that it never has to be fully kept in memory. Overwrites the previous body.
+    ///
+    /// The body is sent with transfer-encoding: chunked and every read from the source is flushed to the client as a chunk. If a content-length header is set the body is instead written as is and the source must yield exactly that many bytes.
+    pub fn body_stream(&mut self, source: impl Read + Send + 'static) {
+        self.body = Vec::new();
+        self.stream = Some(Box::new(source));
+        self.remove_header("content-length");
+    }
+
+    /// Adds a body of a known length that is streamed to the client as it is read, then sets the content-length header to the length. The body is written as is without chunked encoding, and the source must yield at least length bytes
+    pub fn body_stream_sized(&mut self, source: impl Read + Send + 'static, length: u64) {
+        self.body_stream(source);
+        self.header(String::from("content-length"), length.to_string());
+    }
+
+    /// Adds a body that is streamed to the client as the iterator produces it, every item is flushed to the client as a chunk. Overwrites the previous body
+    pub fn body_iter<I>(&mut self, chunks: I)
+    where
+        I: Iterator<Item = Vec<u8>> + Send + 'static,
+    {
+        self.body_stream(ChunkReader {
+            chunks: chunks,
+            current: Vec::new(),
+            position: 0,
+        });
+    }
+
+    /// Check if the response has a streamed body
+    pub fn is_stream(&self) -> bool {
+        self.stream.is_some()
+    }
+
     /// Converts the reponse to an array of bytes in order to write it over the TCP stream. This always writes in the HTTP/1.1 format
     pub fn to_http(self) -> Vec<u8> {
+        return self.to_http_version("1.1");
+    }
+
+    /// Converts the reponse to an array of bytes in the specified http version, which should be the version of the request. Versions other than 1.0 are written as 1.1
+    ///
+    /// A streamed body is read to the end and included in the bytes
+    pub fn to_http_version(self, http_version: &str) -> Vec<u8> {
         let mut http: Vec<u8> = Vec::new();
-        http.extend_from_slice(format!("HTTP/1.1 {} \n", self.status).as_bytes());
-        for (key, value) in self.headers {
-            http.extend_from_slice(format!("{}: {}\n", key, value).as_bytes());
-        }
-        // append newline
-        http.push(10);
-        http.extend_from_slice(&self.body);
+        let _result = self.write_to(&mut http, http_version, false);
         return http;
     }
+
+    /// Writes the response in the specified http version. Without a body if head is true, as when answering a HEAD request.
+    ///
+    /// A date header is added if there is none, and the content-length header is set to the length of the body if it is missing. Streamed bodies are flushed to the writer as they are read
+    pub fn write_to<W: Write>(
+        mut self,
+        writer: &mut W,
+        http_version: &str,
+        head: bool,
+    ) -> io::Result<()> {
+        let version = if http_version == "1.0" { "1.0" } else { "1.1" };
+        if !self.has_header("date") {
+            self.header("date", http_date::format(SystemTime::now()));
+        }
+        // 1xx, 204 and 304 responses never have a body. A content-length is not added to them since it would not describe the body
+        let bodiless = self.status < 200 || self.status == 204 || self.status == 304;
+        let write_body = !head && !bodiless;
+        // Streams without a known length are chunked, HTTP/1.0 clients read them until the connection is closed
+        let chunked =
+            self.stream.is_some() && !self.has_header("content-length") && version == "1.1";
+        if chunked {
+            self.header("transfer-encoding", "chunked");
+        } else if !bodiless && self.stream.is_none() && !self.has_header("content-length") {
+            self.header("content-length", self.body.len().to_string());
+        }
+        let mut http: Vec<u8> = Vec::new();
+        http.extend_from_slice(
+            format!(
+                "HTTP/{} {} {}\r\n",
+                version,
+                self.status,
+                reason_phrase(self.status)
+            )
+            .as_bytes(),
+        );
+        for (key, value) in &self.headers {
+            http.extend_from_slice(format!("{}: {}\r\n", key, value).as_bytes());
+        }
+        // append empty line that ends the headers
+        http.extend_from_slice(b"\r\n");
+        if write_body && self.stream.is_none() {
+            http.extend_from_slice(&self.body);
+        }
+        writer.write_all(&http)?;
+        if let (true, Some(mut stream)) = (write_body, self.stream.take()) {
+            // A stream with a content-length is written as is and must yield exactly that many bytes
+            let mut remaining: Option<u64> = if chunked {
+                None
+            } else {
+                self.headers
+                    .iter()
+                    .find(|(key, _)| key.eq_ignore_ascii_case("content-length"))
+                    .and_then(|(_, value)| value.trim().parse().ok())
+            };
+            let mut buffer = vec![0; STREAM_BUFFER_SIZE];
+            loop {
+                let limit = match remaining {
+                    Some(0) => break,
+                    Some(remaining) => buffer.len().min(remaining as usize),
+                    None => buffer.len(),
+                };
+                let read = match stream.read(&mut buffer[..limit]) {
+                    Ok(0) => break,
+                    Ok(read) => read,
+                    Err(error) if error.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(error) => return Err(error),
+                };
+                if chunked {
+                    writer.write_all(format!("{:x}\r\n", read).as_bytes())?;
+                    writer.write_all(&buffer[..read])?;
+                    writer.write_all(b"\r\n")?;
+                } else {
+                    writer.write_all(&buffer[..read])?;
+                }
+                writer.flush()?;
+                if let Some(remaining) = remaining.as_mut() {
+                    *remaining -= read as u64;
+                }
+            }
+            if chunked {
+                writer.write_all(b"0\r\n\r\n")?;
+            }
+            if let Some(1..) = remaining {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "Streamed body ended before its content-length",
+                ));
+            }
+        }
+        writer.flush()
+    }
+
+    /// Check if a header is set, ignoring the case of the header name
+    pub fn has_header(&self, name: &str) -> bool {
+        self.headers
+            .keys()
+            .any(|key| key.eq_ignore_ascii_case(name))
+    }
+
+    /// Removes a header regardless of the case it was added with
+    pub fn remove_header(&mut self, name: &str) {
+        self.headers
+            .retain(|key, _| !key.eq_ignore_ascii_case(name));
+    }
+}
+
+/// The most bytes read from a streamed body before they are written to the client
+const STREAM_BUFFER_SIZE: usize = 16 * 1024;
+
+/// Adapts an iterator of byte vectors to a reader, every read returns bytes from a single item so that items are flushed as separate chunks
+struct ChunkReader<I> {
+    chunks: I,
+    current: Vec<u8>,
+    position: usize,
+}
+
+impl<I: Iterator<Item = Vec<u8>>> Read for ChunkReader<I> {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        while self.position >= self.current.len() {
+            match self.chunks.next() {
+                Some(chunk) => {
+                    self.current = chunk;
+                    self.position = 0;
+                }
+                None => return Ok(0),
+            }
+        }
+        let amount = buffer.len().min(self.current.len() - self.position);
+        buffer[..amount].copy_from_slice(&self.current[self.position..self.position + amount]);
+        self.position += amount;
+        Ok(amount)
+    }
+}
+
+/// Returns the reason phrase of a status code, for example 404 -> "Not Found". Unregistered status codes return an empty string
+pub fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        100 => "Continue",
+        101 => "Switching Protocols",
+        102 => "Processing",
+        103 => "Early Hints",
+        200 => "OK",
+        201 => "Created",
+        202 => "Accepted",
+        203 => "Non-Authoritative Information",
+        204 => "No Content",
+        205 => "Reset Content",
+        206 => "Partial Content",
+        207 => "Multi-Status",
+        208 => "Already Reported",
+        226 => "IM Used",
+        300 => "Multiple Choices",
+        301 => "Moved Permanently",
+        302 => "Found",
+        303 => "See Other",
+        304 => "Not Modified",
+        305 => "Use Proxy",
+        307 => "Temporary Redirect",
+        308 => "Permanent Redirect",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        402 => "Payment Required",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        406 => "Not Acceptable",
+        407 => "Proxy Authentication Required",
+        408 => "Request Timeout",
+        409 => "Conflict",
+        410 => "Gone",
+        411 => "Length Required",
+        412 => "Precondition Failed",
+        413 => "Content Too Large",
+        414 => "URI Too Long",
+        415 => "Unsupported Media Type",
+        416 => "Range Not Satisfiable",
+        417 => "Expectation Failed",
+        418 => "I'm a teapot",
+        421 => "Misdirected Request",
+        422 => "Unprocessable Content",
+        423 => "Locked",
+        424 => "Failed Dependency",
+        425 => "Too Early",
+        426 => "Upgrade Required",
+        428 => "Precondition Required",
+        429 => "Too Many Requests",
+        431 => "Request Header Fields Too Large",
+        451 => "Unavailable For Legal Reasons",
+        500 => "Internal Server Error",
+        501 => "Not Implemented",
+        502 => "Bad Gateway",
+        503 => "Service Unavailable",
+        504 => "Gateway Timeout",
+        505 => "HTTP Version Not Supported",
+        506 => "Variant Also Negotiates",
+        507 => "Insufficient Storage",
+        508 => "Loop Detected",
+        510 => "Not Extended",
+        511 => "Network Authentication Required",
+        _ => "",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(response: Response, http_version: &str, head: bool) -> String {
+        let mut http = Vec::new();
+        response.write_to(&mut http, http_version, head).unwrap();
+        return String::from_utf8(http).unwrap();
+    }
+
+    fn header<'a>(http: &'a str, name: &str) -> Option<&'a str> {
+        let head = &http[..http.find("\r\n\r\n").unwrap()];
+        return head
+            .split("\r\n")
+            .skip(1)
+            .find_map(|line| line.strip_prefix(name)?.strip_prefix(": "));
+    }
+
+    #[test]
+    fn writes_crlf_status_line_and_headers() {
+        let mut response = Response::new(404, Vec::new(), HashMap::new());
+        response.body("gone");
+        let http = write(response, "1.1", false);
+        assert!(http.starts_with("HTTP/1.1 404 Not Found\r\n"));
+        assert!(http.ends_with("\r\n\r\ngone"));
+        assert_eq!(header(&http, "content-length"), Some("4"));
+        assert!(header(&http, "date").is_some());
+        let head = &http[..http.find("\r\n\r\n").unwrap()];
+        assert!(!head.replace("\r\n", "").contains('\n'));
+    }
+
+    #[test]
+    fn adds_content_length_to_empty_bodies() {
+        let http = write(Response::new(200, Vec::new(), HashMap::new()), "1.1", false);
+        assert_eq!(header(&http, "content-length"), Some("0"));
+        assert!(http.ends_with("\r\n\r\n"));
+    }
+
+    #[test]
+    fn writes_no_body_for_bodiless_statuses() {
+        for status in [204, 304] {
+            let http = write(
+                Response::new(status, b"body".to_vec(), HashMap::new()),
+                "1.1",
+                false,
+            );
+            assert_eq!(header(&http, "content-length"), None, "status {}", status);
+            assert!(http.ends_with("\r\n\r\n"), "status {}", status);
+        }
+    }
+
+    #[test]
+    fn writes_no_body_for_head_requests() {
+        let mut response = Response::new(200, Vec::new(), HashMap::new());
+        response.body("hello");
+        let http = write(response, "1.1", true);
+        assert_eq!(header(&http, "content-length"), Some("5"));
+        assert!(http.ends_with("\r\n\r\n"));
+    }
+
+    #[test]
+    fn answers_http_1_0_with_a_1_0_status_line() {
+        let response = Response::new(200, Vec::new(), HashMap::new());
+        assert!(write(response, "1.0", false).starts_with("HTTP/1.0 200 OK\r\n"));
+        let response = Response::new(200, Vec::new(), HashMap::new());
+        assert!(write(response, "2.0", false).starts_with("HTTP/1.1 200 OK\r\n"));
+    }
+
+    #[test]
+    fn frames_iterator_items_as_chunks() {
+        let mut response = Response::new(200, Vec::new(), HashMap::new());
+        response.body_iter(
+            vec![b"hello".to_vec(), Vec::new(), b" streamed world".to_vec()].into_iter(),
+        );
+        let http = write(response, "1.1", false);
+        assert_eq!(header(&http, "transfer-encoding"), Some("chunked"));
+        assert_eq!(header(&http, "content-length"), None);
+        assert!(http.ends_with("\r\n\r\n5\r\nhello\r\nf\r\n streamed world\r\n0\r\n\r\n"));
+    }
+
+    #[test]
+    fn writes_sized_streams_as_is() {
+        let mut response = Response::new(200, Vec::new(), HashMap::new());
+        response.body_stream_sized(&b"hello world"[..], 5);
+        let http = write(response, "1.1", false);
+        assert_eq!(header(&http, "transfer-encoding"), None);
+        assert_eq!(header(&http, "content-length"), Some("5"));
+        assert!(http.ends_with("\r\n\r\nhello"));
+    }
+
+    #[test]
+    fn fails_when_a_sized_stream_ends_early() {
+        let mut response = Response::new(200, Vec::new(), HashMap::new());
+        response.body_stream_sized(&b"short"[..], 10);
+        let mut http = Vec::new();
+        let error = response.write_to(&mut http, "1.1", false).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+    }
 }
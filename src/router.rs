@@ -0,0 +1,168 @@
+use std::collections::HashMap;
+
+/// Removes the trailing / from a path so that pathing is agnostic towards /example/ or /example. The root path / is kept as is
+pub fn normalize_path(path: &str) -> String {
+    if path.is_empty() {
+        return String::from("/");
+    }
+    let mut path_string = String::from(path);
+    if path_string.len() > 1 && path_string.ends_with('/') {
+        path_string.pop();
+    }
+    path_string
+}
+
+/// Matches a route pattern against a request path. Segments starting with : capture the corresponding segment of the path.
+///
+/// For example /user/:id matched against /user/12 returns Some with id as key and 12 as value, while /user/12/posts returns None
+pub fn match_route(pattern: &str, path: &str) -> Option<HashMap<String, String>> {
+    let pattern_segments: Vec<&str> = pattern.split('/').collect();
+    let path_segments: Vec<&str> = path.split('/').collect();
+    if pattern_segments.len() != path_segments.len() {
+        return None;
+    }
+    let mut path_params = HashMap::new();
+    for (pattern_segment, path_segment) in pattern_segments.iter().zip(path_segments.iter()) {
+        match param_name(pattern_segment) {
+            Some(name) => {
+                // A parameter never matches an empty segment, /user/ should not match /user/:id
+                if path_segment.is_empty() {
+                    return None;
+                }
+                path_params.insert(String::from(name), String::from(*path_segment));
+            }
+            None => {
+                if pattern_segment != path_segment {
+                    return None;
+                }
+            }
+        }
+    }
+    Some(path_params)
+}
+
+/// Finds the route that matches the path, returning the value stored for it and the captured path parameters.
+///
+/// An exact match is always preferred. If several patterns match, the one with literal segments earliest in the path wins, so /user/new is chosen over /user/:id
+pub fn find_route<'a, T>(
+    routes: &'a HashMap<String, T>,
+    path: &str,
+) -> Option<(&'a T, HashMap<String, String>)> {
+    if let Some(value) = routes.get(path) {
+        return Some((value, HashMap::new()));
+    }
+    let mut best: Option<(Vec<bool>, &T, HashMap<String, String>)> = None;
+    for (pattern, value) in routes {
+        if !pattern.contains(':') {
+            continue;
+        }
+        if let Some(path_params) = match_route(pattern, path) {
+            let literals: Vec<bool> = pattern
+                .split('/')
+                .map(|segment| param_name(segment).is_none())
+                .collect();
+            let better = match &best {
+                Some((best_literals, _, _)) => literals > *best_literals,
+                None => true,
+            };
+            if better {
+                best = Some((literals, value, path_params));
+            }
+        }
+    }
+    best.map(|(_, value, path_params)| (value, path_params))
+}
+
+/// Returns the parameter name if the segment is a path parameter such as :id
+fn param_name(segment: &str) -> Option<&str> {
+    if segment.len() > 1 && segment.starts_with(':') {
+        return Some(&segment[1..]);
+    }
+    None
+}
+
+/// The key used for routes that accept any request method
+pub const ANY_METHOD: &str = "*";
+
+/// Picks the route registered for the request method on a path. HEAD requests fall back to the GET route and routes registered for any method match every request.
+///
+/// Returns the methods that are allowed on the path if none matches, sorted so that they can be written directly to an Allow header
+pub fn find_method<'a, T>(
+    methods: &'a HashMap<String, T>,
+    method: &str,
+) -> Result<&'a T, Vec<String>> {
+    if let Some(value) = methods.get(method) {
+        return Ok(value);
+    }
+    if method == "HEAD" {
+        if let Some(value) = methods.get("GET") {
+            return Ok(value);
+        }
+    }
+    if let Some(value) = methods.get(ANY_METHOD) {
+        return Ok(value);
+    }
+    let mut allowed: Vec<String> = methods.keys().cloned().collect();
+    if methods.contains_key("GET") && !methods.contains_key("HEAD") {
+        allowed.push(String::from("HEAD"));
+    }
+    allowed.sort();
+    Err(allowed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn routes(patterns: &[&str]) -> HashMap<String, String> {
+        patterns
+            .iter()
+            .map(|pattern| (String::from(*pattern), String::from(*pattern)))
+            .collect()
+    }
+
+    #[test]
+    fn normalizes_paths() {
+        assert_eq!(normalize_path(""), "/");
+        assert_eq!(normalize_path("/"), "/");
+        assert_eq!(normalize_path("/example/"), "/example");
+        assert_eq!(normalize_path("/example"), "/example");
+    }
+
+    #[test]
+    fn captures_path_params() {
+        let params = match_route("/user/:id/posts/:post", "/user/12/posts/3").unwrap();
+        assert_eq!(params["id"], "12");
+        assert_eq!(params["post"], "3");
+        assert!(match_route("/user/:id", "/user/12/posts").is_none());
+        assert!(match_route("/user/:id", "/user/").is_none());
+        assert!(match_route("/user/:id", "/users/12").is_none());
+    }
+
+    #[test]
+    fn prefers_exact_and_literal_routes() {
+        let routes = routes(&["/user/:id", "/user/new", "/:section/new", "/user/:id/:tab"]);
+        let (route, params) = find_route(&routes, "/user/new").unwrap();
+        assert_eq!(route, "/user/new");
+        assert!(params.is_empty());
+        assert_eq!(find_route(&routes, "/user/12").unwrap().0, "/user/:id");
+        assert_eq!(find_route(&routes, "/blog/new").unwrap().0, "/:section/new");
+        assert_eq!(
+            find_route(&routes, "/user/12/likes").unwrap().1["tab"],
+            "likes"
+        );
+        assert!(find_route(&routes, "/blog/12").is_none());
+    }
+
+    #[test]
+    fn picks_methods() {
+        let methods = routes(&["GET", "POST"]);
+        assert_eq!(find_method(&methods, "HEAD").unwrap(), "GET");
+        assert_eq!(
+            find_method(&methods, "DELETE").unwrap_err(),
+            vec!["GET", "HEAD", "POST"]
+        );
+        let any = routes(&[ANY_METHOD]);
+        assert_eq!(find_method(&any, "PATCH").unwrap(), ANY_METHOD);
+    }
+}
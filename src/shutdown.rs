@@ -0,0 +1,92 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// Counts the SIGINT and SIGTERM signals the process received, incremented by the signal handler
+static SIGNALS_RECEIVED: AtomicUsize = AtomicUsize::new(0);
+
+/// Marks a handle that does not shut down on signals
+const NOT_LISTENING: usize = usize::MAX;
+
+/// Stops a running server from another thread, created with Spot::shutdown_handle.
+///
+/// After shutdown is called the server stops accepting connections, lets the requests that are being handled finish and returns from bind. Idle keep-alive connections are closed
+#[derive(Clone)]
+pub struct ShutdownHandle {
+    requested: Arc<AtomicBool>,
+    // The signal count when the server started listening for signals, a signal received after that shuts the server down
+    signals_before: Arc<AtomicUsize>,
+}
+
+impl Default for ShutdownHandle {
+    fn default() -> ShutdownHandle {
+        return ShutdownHandle::new();
+    }
+}
+
+impl ShutdownHandle {
+    pub(crate) fn new() -> ShutdownHandle {
+        return ShutdownHandle {
+            requested: Arc::new(AtomicBool::new(false)),
+            signals_before: Arc::new(AtomicUsize::new(NOT_LISTENING)),
+        };
+    }
+
+    /// Tell the server to shut down, returns immediately without waiting for the server to stop
+    pub fn shutdown(&self) {
+        self.requested.store(true, Ordering::SeqCst);
+    }
+
+    /// Check if the server has been told to shut down, either through a handle or by a signal it listens for
+    pub fn is_shutdown(&self) -> bool {
+        if self.requested.load(Ordering::SeqCst) {
+            return true;
+        }
+        let signals_before = self.signals_before.load(Ordering::SeqCst);
+        return signals_before != NOT_LISTENING
+            && SIGNALS_RECEIVED.load(Ordering::SeqCst) > signals_before;
+    }
+
+    /// Shut the server down on the next SIGINT or SIGTERM. Signals received before, or by other servers in the process, are ignored
+    pub(crate) fn listen_for_signals(&self) {
+        self.signals_before
+            .store(SIGNALS_RECEIVED.load(Ordering::SeqCst), Ordering::SeqCst);
+        handle_signals();
+    }
+}
+
+/// Shut down the server when the process receives SIGINT or SIGTERM. A second signal ends the process immediately
+#[cfg(unix)]
+fn handle_signals() {
+    use std::os::raw::c_int;
+
+    const SIGINT: c_int = 2;
+    const SIGTERM: c_int = 15;
+    const SIG_DFL: usize = 0;
+    const SIG_ERR: usize = usize::MAX;
+
+    extern "C" {
+        fn signal(signum: c_int, handler: usize) -> usize;
+    }
+
+    extern "C" fn on_signal(signum: c_int) {
+        // Only an atomic increment is safe in a signal handler, the servers listening for signals compare the count against the one they started with
+        SIGNALS_RECEIVED.fetch_add(1, Ordering::SeqCst);
+        // Restore the default action so that a second signal kills a server that hangs while shutting down
+        unsafe {
+            signal(signum, SIG_DFL);
+        }
+    }
+
+    for signum in [SIGINT, SIGTERM] {
+        let previous = unsafe { signal(signum, on_signal as extern "C" fn(c_int) as usize) };
+        if previous == SIG_ERR {
+            println!("Failed to install handler for signal {}", signum);
+        }
+    }
+}
+
+/// Shut down the server when the process receives SIGINT or SIGTERM, only supported on unix
+#[cfg(not(unix))]
+fn handle_signals() {
+    println!("Warning: Shutting down on signals is only supported on unix");
+}
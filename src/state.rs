@@ -0,0 +1,36 @@
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Application state shared between all the worker threads, values are stored and retrieved by their type
+#[derive(Default)]
+pub struct State {
+    values: HashMap<TypeId, Arc<dyn Any + Send + Sync>>,
+}
+
+impl State {
+    /// Creates an empty state
+    pub fn new() -> State {
+        return State {
+            values: HashMap::new(),
+        };
+    }
+
+    /// Stores a value, replacing any previous value of the same type
+    pub fn insert<T: Send + Sync + 'static>(&mut self, value: T) {
+        self.values.insert(TypeId::of::<T>(), Arc::new(value));
+    }
+
+    /// Returns a reference to the stored value of the specified type if there is one
+    pub fn get<T: Send + Sync + 'static>(&self) -> Option<&T> {
+        match self.values.get(&TypeId::of::<T>()) {
+            Some(value) => value.downcast_ref::<T>(),
+            None => None,
+        }
+    }
+
+    /// Check if a value of the specified type is stored
+    pub fn contains<T: Send + Sync + 'static>(&self) -> bool {
+        self.values.contains_key(&TypeId::of::<T>())
+    }
+}
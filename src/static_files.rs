@@ -0,0 +1,1026 @@
+use crate::http_date;
+use crate::range::{self, Ranges};
+use crate::request::Request;
+use crate::response::Response;
+use crate::router;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs;
+use std::io::{self, Cursor, Read, Seek, SeekFrom};
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A directory whose files are served under a url prefix, created with Spot::public, Spot::public_at or Spot::embedded_at.
+///
+/// Paths in directories on disk are resolved against the root when they are requested, so files added after the server started are served aswell
+pub struct StaticMount {
+    // The url prefix the directory is mounted under, without a trailing / so the root is an empty string
+    prefix: String,
+    // Where the files are read from
+    source: Source,
+    // Set if the directory could not be opened, the mount then serves no files
+    error: Option<String>,
+    // The cache-control header sent with every file
+    cache_control: Option<String>,
+    // Whether directories without an index.html are answered with a listing of their contents
+    listing: bool,
+    // Whether precompressed .br and .gz siblings of files are served to clients that accept them
+    precompressed: bool,
+    // The file, relative to the root, that answers html requests matching no file
+    fallback: Option<String>,
+}
+
+/// Where the files of a mount are read from
+enum Source {
+    // The canonical absolute path of a directory on disk
+    Directory(PathBuf),
+    // Files embedded in the binary keyed by their path relative to the root, such as new_folder/info.txt, and the paths of the directories that contain them
+    Embedded(HashMap<String, EmbeddedFile>, HashSet<String>),
+}
+
+/// A file embedded in the binary with include_assets!
+struct EmbeddedFile {
+    contents: &'static [u8],
+    // Embedded files have no modification time, so the etag is a hash of the contents
+    etag: String,
+}
+
+/// A file or directory found in a mount
+enum Entry {
+    File(StaticFile),
+    // The path of the directory on disk, embedded directories have none
+    Directory(Option<PathBuf>),
+}
+
+/// A file that can be served, either from disk or embedded in the binary
+pub(crate) struct StaticFile {
+    content: Content,
+    length: u64,
+    modified: Option<SystemTime>,
+    etag: Option<String>,
+}
+
+/// The contents of a static file
+enum Content {
+    Disk(PathBuf),
+    Embedded(&'static [u8]),
+}
+
+/// A readable and seekable file body that can be sent to the worker writing the response
+trait FileBody: Read + Seek + Send {}
+
+impl<T: Read + Seek + Send> FileBody for T {}
+
+/// The precompressed siblings that are looked for, in order of preference, as (content-encoding, file ending)
+const PRECOMPRESSED_ENCODINGS: [(&str, &str); 2] = [("br", ".br"), ("gzip", ".gz")];
+
+/// The file that is served when a directory is requested
+const INDEX_FILE: &str = "index.html";
+
+impl StaticMount {
+    /// Creates a mount that serves the directory under the url prefix
+    pub(crate) fn new(prefix: &str, directory: &Path) -> StaticMount {
+        let mut error = None;
+        let root = match directory.canonicalize() {
+            Ok(root) => {
+                if !root.is_dir() {
+                    error = Some(format!("{} is not a directory", directory.display()));
+                }
+                root
+            }
+            Err(canonicalize_error) => {
+                error = Some(format!(
+                    "Failed to open static directory {}: {}",
+                    directory.display(),
+                    canonicalize_error
+                ));
+                directory.to_path_buf()
+            }
+        };
+        let mut mount = StaticMount::with_source(prefix, Source::Directory(root));
+        mount.error = error;
+        return mount;
+    }
+
+    /// Creates a mount that serves files embedded in the binary under the url prefix. The files are given as pairs of paths relative to the root and their contents, as created by include_assets!
+    pub(crate) fn embedded(
+        prefix: &str,
+        files: &'static [(&'static str, &'static [u8])],
+    ) -> StaticMount {
+        let mut embedded_files = HashMap::new();
+        let mut directories = HashSet::new();
+        for (path, contents) in files {
+            let path = path.trim_start_matches('/');
+            // Every parent of the file is a directory, new_folder/info.txt is in new_folder
+            let mut parent = path;
+            while let Some((directory, _)) = parent.rsplit_once('/') {
+                directories.insert(String::from(directory));
+                parent = directory;
+            }
+            embedded_files.insert(
+                String::from(path),
+                EmbeddedFile {
+                    contents,
+                    etag: format!("\"{:x}-{:x}\"", fnv_hash(contents), contents.len()),
+                },
+            );
+        }
+        return StaticMount::with_source(prefix, Source::Embedded(embedded_files, directories));
+    }
+
+    /// Creates a mount with the default settings
+    fn with_source(prefix: &str, source: Source) -> StaticMount {
+        let mut prefix = String::from(prefix.trim_end_matches('/'));
+        if !prefix.is_empty() && !prefix.starts_with('/') {
+            prefix.insert(0, '/');
+        }
+        return StaticMount {
+            prefix,
+            source,
+            error: None,
+            cache_control: None,
+            listing: false,
+            precompressed: false,
+            fallback: None,
+        };
+    }
+
+    /// Sets the cache-control header that is sent with every file in the directory, for example "public, max-age=3600"
+    pub fn cache_control(&mut self, value: &str) -> &mut StaticMount {
+        self.cache_control = Some(String::from(value));
+        self
+    }
+
+    /// Enables or disables html listings of directories that have no index.html. Listings are disabled by default
+    pub fn listing(&mut self, enabled: bool) -> &mut StaticMount {
+        self.listing = enabled;
+        self
+    }
+
+    /// Enables or disables serving precompressed files. When enabled a request for app.js is answered with app.js.br or app.js.gz if the file exists and the client accepts the encoding, with the content type of app.js. Disabled by default
+    pub fn precompressed(&mut self, enabled: bool) -> &mut StaticMount {
+        self.precompressed = enabled;
+        self
+    }
+
+    /// Sets a file, relative to the directory, that answers GET requests which accept html but match no file. Used by single page apps with client side routing, for example fallback("index.html") answers /dashboard/settings with index.html.
+    ///
+    /// Routes always take precedence over the fallback, and requests for missing scripts or images that do not accept html are still answered with 404
+    pub fn fallback(&mut self, file: &str) -> &mut StaticMount {
+        self.fallback = Some(String::from(file.trim_start_matches('/')));
+        self
+    }
+
+    /// Returns the reason the directory can not be served, if it could not be opened
+    pub(crate) fn error(&self) -> Option<&str> {
+        self.error.as_deref()
+    }
+
+    /// The url prefix the directory is mounted under
+    pub(crate) fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    /// Check if the url path lies under the mount prefix
+    pub(crate) fn matches(&self, url_path: &str) -> bool {
+        self.relative_path(url_path).is_some()
+    }
+
+    /// Check if a url path refers to a file or directory in the mount
+    pub(crate) fn contains(&self, url_path: &str) -> bool {
+        self.lookup(url_path).is_some()
+    }
+
+    /// Finds the file or directory a url path refers to. Returns None if the path is outside the mount, does not exist, or would escape the root through .. or a symbolic link
+    fn lookup(&self, url_path: &str) -> Option<Entry> {
+        if self.error.is_some() {
+            return None;
+        }
+        let segments = self.segments(url_path)?;
+        match &self.source {
+            Source::Directory(root) => {
+                let mut path = root.clone();
+                path.extend(segments);
+                // Canonicalizing resolves symbolic links, the result has to stay inside the root
+                let canonical = path.canonicalize().ok()?;
+                if !canonical.starts_with(root) {
+                    return None;
+                }
+                if canonical.is_dir() {
+                    return Some(Entry::Directory(Some(canonical)));
+                }
+                StaticFile::from_disk(&canonical).map(Entry::File)
+            }
+            Source::Embedded(files, directories) => {
+                let key = segments.join("/");
+                if let Some(file) = files.get(&key) {
+                    return Some(Entry::File(StaticFile {
+                        content: Content::Embedded(file.contents),
+                        length: file.contents.len() as u64,
+                        modified: None,
+                        etag: Some(file.etag.clone()),
+                    }));
+                }
+                if key.is_empty() || directories.contains(&key) {
+                    return Some(Entry::Directory(None));
+                }
+                None
+            }
+        }
+    }
+
+    /// Splits the url path into the decoded path segments below the mount prefix, without empty and . segments. Returns None if the path is outside the mount or contains ..
+    fn segments(&self, url_path: &str) -> Option<Vec<String>> {
+        let relative = percent_decode(self.relative_path(url_path)?)?;
+        let mut segments = Vec::new();
+        for component in Path::new(&relative).components() {
+            match component {
+                Component::Normal(segment) => segments.push(String::from(segment.to_str()?)),
+                Component::RootDir | Component::CurDir => {}
+                // .. and windows drive prefixes are never allowed in urls
+                Component::ParentDir | Component::Prefix(_) => return None,
+            }
+        }
+        Some(segments)
+    }
+
+    /// The decoded and normalized url path that a request for a file in the mount refers to, for example /%73ecret//./key.txt becomes /secret/key.txt. Returns None if the path is outside the mount or contains ..
+    pub(crate) fn canonical_path(&self, url_path: &str) -> Option<String> {
+        let mut path = self.prefix.clone();
+        for segment in self.segments(url_path)? {
+            path.push('/');
+            path.push_str(&segment);
+        }
+        Some(router::normalize_path(&path))
+    }
+
+    /// The url a directory is redirected to, built from the prefix and the normalized path so that a request for //example can not redirect to the host example
+    fn directory_url(&self, url_path: &str) -> Option<String> {
+        let mut url = self.prefix.clone();
+        for segment in self.segments(url_path)? {
+            url.push('/');
+            url.push_str(&percent_encode(&segment));
+        }
+        url.push('/');
+        Some(url)
+    }
+
+    /// Answers a request for a file in the mount. The response is left as is if the file does not exist
+    ///
+    /// Directories are answered with their index.html, or a listing of their contents if listings are enabled. Requests for a directory without a trailing / are redirected to the path with one, so that relative links in the page work.
+    ///
+    /// If a fallback file is set, GET requests that accept html and match no file are answered with the fallback
+    pub(crate) fn serve(&self, request: &Request, mut response: Response) -> Response {
+        let url_path = request.url.split('?').next().unwrap_or_default();
+        let (file, file_url) = match self.lookup(url_path) {
+            Some(Entry::Directory(directory)) => {
+                if !url_path.ends_with('/') {
+                    let query = &request.url[url_path.len()..];
+                    let location = match self.directory_url(url_path) {
+                        Some(location) => location,
+                        None => return response,
+                    };
+                    response.status(301);
+                    response.header("location", format!("{}{}", location, query));
+                    return response;
+                }
+                let index_url = format!("{}{}", url_path, INDEX_FILE);
+                match (self.lookup(&index_url), directory) {
+                    (Some(Entry::File(index)), _) => (index, index_url),
+                    (_, Some(directory)) if self.listing => {
+                        return self.directory_listing(&directory, url_path, response);
+                    }
+                    _ => match self.fallback_file(request) {
+                        Some(fallback) => fallback,
+                        None => return response,
+                    },
+                }
+            }
+            Some(Entry::File(file)) => (file, String::from(url_path)),
+            None => match self.fallback_file(request) {
+                Some(fallback) => fallback,
+                None => return response,
+            },
+        };
+        // The content type is always that of the requested file, also when a precompressed sibling is sent
+        let type_path = PathBuf::from(&file_url);
+        let mut response = match self.precompressed_variant(&file_url, request) {
+            Some((encoded, encoding)) => {
+                serve_file_as(&encoded, &type_path, Some(encoding), request, response)
+            }
+            None => serve_file_as(&file, &type_path, None, request, response),
+        };
+        if self.precompressed {
+            // Caches have to store a version per encoding since the body depends on accept-encoding
+            response.header("vary", "accept-encoding");
+        }
+        if let Some(cache_control) = &self.cache_control {
+            if response.status == 200 || response.status == 206 || response.status == 304 {
+                response.header("cache-control", cache_control);
+            }
+        }
+        return response;
+    }
+
+    /// Returns the path and url of the fallback file if the request should be answered with it. Only GET and HEAD requests that accept html get the fallback, so missing scripts and images are still answered with 404
+    fn fallback_file(&self, request: &Request) -> Option<(StaticFile, String)> {
+        let fallback = self.fallback.as_ref()?;
+        if request.method != "GET" && request.method != "HEAD" {
+            return None;
+        }
+        let accept = request.headers.get("accept")?;
+        let accepts_html = accept.split(',').any(|item| {
+            let mut parts = item.split(';');
+            let media_type = parts.next().unwrap_or_default().trim();
+            let refused = parts.any(|parameter| match parameter.trim().strip_prefix("q=") {
+                Some(quality) => quality.trim().parse().unwrap_or(1.0) <= 0.0,
+                None => false,
+            });
+            media_type.eq_ignore_ascii_case("text/html") && !refused
+        });
+        if !accepts_html {
+            return None;
+        }
+        let fallback_url = format!("{}/{}", self.prefix, fallback);
+        match self.lookup(&fallback_url) {
+            Some(Entry::File(file)) => Some((file, fallback_url)),
+            _ => None,
+        }
+    }
+
+    /// Finds the precompressed sibling of a file that the client accepts, returning its path and content encoding
+    fn precompressed_variant(
+        &self,
+        file_url: &str,
+        request: &Request,
+    ) -> Option<(StaticFile, &'static str)> {
+        if !self.precompressed {
+            return None;
+        }
+        let accept_encoding = request.headers.get("accept-encoding")?;
+        for (encoding, file_ending) in PRECOMPRESSED_ENCODINGS.iter() {
+            if !accepts_encoding(accept_encoding, encoding) {
+                continue;
+            }
+            // The sibling is resolved like any other path so it can not escape the root
+            if let Some(Entry::File(file)) = self.lookup(&format!("{}{}", file_url, file_ending)) {
+                return Some((file, encoding));
+            }
+        }
+        None
+    }
+
+    /// Responds with an html page that links to every file and directory in the directory, along with their sizes and modification times. Only directories on disk can be listed
+    fn directory_listing(&self, directory: &Path, url_path: &str, mut res: Response) -> Response {
+        let root = match &self.source {
+            Source::Directory(root) => root,
+            Source::Embedded(_, _) => return res,
+        };
+        let entries = match fs::read_dir(directory) {
+            Ok(entries) => entries,
+            Err(error) => {
+                println!("{}", error);
+                res.status(500);
+                return res;
+            }
+        };
+        // Directories are listed first, then files, both sorted by name
+        let mut items: Vec<(bool, String, String, String)> = Vec::new();
+        for entry in entries.flatten() {
+            let name = match entry.file_name().into_string() {
+                Ok(name) => name,
+                Err(_error) => continue,
+            };
+            // Symbolic links that point outside the root are never shown
+            let path = match entry.path().canonicalize() {
+                Ok(path) if path.starts_with(root) => path,
+                _ => continue,
+            };
+            let metadata = match fs::metadata(&path) {
+                Ok(metadata) => metadata,
+                Err(_error) => continue,
+            };
+            let is_dir = metadata.is_dir();
+            let size = if is_dir {
+                String::from("-")
+            } else {
+                metadata.len().to_string()
+            };
+            let modified = match metadata.modified() {
+                Ok(modified) => http_date::format(modified),
+                Err(_error) => String::from("-"),
+            };
+            items.push((!is_dir, name, size, modified));
+        }
+        items.sort();
+        let title = html_escape(&percent_decode(url_path).unwrap_or_default());
+        let mut html = format!(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Index of {0}</title>\n</head>\n<body>\n<h1>Index of {0}</h1>\n<table>\n<tr><th>Name</th><th>Size</th><th>Last modified</th></tr>\n",
+            title
+        );
+        if url_path.len() > self.prefix.len() + 1 {
+            html.push_str("<tr><td><a href=\"../\">../</a></td><td>-</td><td>-</td></tr>\n");
+        }
+        for (is_file, name, size, modified) in items {
+            let slash = if is_file { "" } else { "/" };
+            html.push_str(&format!(
+                "<tr><td><a href=\"{}{}\">{}{}</a></td><td>{}</td><td>{}</td></tr>\n",
+                percent_encode(&name),
+                slash,
+                html_escape(&name),
+                slash,
+                size,
+                modified
+            ));
+        }
+        html.push_str("</table>\n</body>\n</html>\n");
+        res.status(200);
+        res.body_bytes(html.into_bytes());
+        res.header("content-type", "text/html; charset=utf-8");
+        return res;
+    }
+
+    /// Returns the part of the url path after the prefix, or None if the path is not under the prefix
+    fn relative_path<'a>(&self, url_path: &'a str) -> Option<&'a str> {
+        let rest = url_path.strip_prefix(self.prefix.as_str())?;
+        if rest.is_empty() || rest.starts_with('/') {
+            return Some(rest);
+        }
+        None
+    }
+}
+
+/// Responds with the contents of a file and its content type. The response is left as is if the path is not a file
+pub(crate) fn serve_file(path: &Path, request: &Request, res: Response) -> Response {
+    match StaticFile::from_disk(path) {
+        Some(file) => serve_file_as(&file, path, None, request, res),
+        None => res,
+    }
+}
+
+/// Responds with the contents of the file, using the content type of the file ending of type_path. The content-encoding header is set if the file is an encoded version of type_path
+///
+/// Files are sent with an etag and last-modified header, requests with a matching if-none-match or if-modified-since header are answered with 304 Not Modified.
+///
+/// Range requests are answered with 206 Partial Content, or 416 Range Not Satisfiable if no requested range lies inside the file
+fn serve_file_as(
+    file: &StaticFile,
+    type_path: &Path,
+    encoding: Option<&str>,
+    request: &Request,
+    mut res: Response,
+) -> Response {
+    let file_ending = match type_path.extension() {
+        Some(extension) => extension.to_str().unwrap_or_default(),
+        None => "",
+    };
+    let file_type = request.file_parser.get_type(file_ending);
+    if let Some(encoding) = encoding {
+        res.header("content-encoding", encoding);
+    }
+    let validators = Validators::new(file);
+    if let Some(etag) = &validators.etag {
+        res.header("etag", etag);
+    }
+    if let Some(last_modified) = &validators.last_modified {
+        res.header("last-modified", last_modified);
+    }
+    if validators.not_modified(request) {
+        res.status(304);
+        return res;
+    }
+    let length = file.length;
+    let mut body = match file.open() {
+        Ok(body) => body,
+        Err(error) => {
+            println!("{}", error);
+            res.status(500);
+            return res;
+        }
+    };
+    res.header("accept-ranges", "bytes");
+    let ranges = match request.headers.get("range") {
+        Some(range) if validators.if_range_matches(request) => range::parse(range, length),
+        _ => Ranges::Full,
+    };
+    // The file is streamed to the client so files on disk are never fully read into memory
+    match ranges {
+        Ranges::Full => {
+            res.status(200);
+            res.body_stream_sized(body, length);
+            res.header("content-type", file_type);
+        }
+        Ranges::Unsatisfiable => {
+            res.status(416);
+            res.header("content-range", format!("bytes */{}", length));
+            res.body_bytes(Vec::new());
+        }
+        Ranges::Partial(ranges) => {
+            res.status(206);
+            if ranges.len() == 1 {
+                let (start, end) = ranges[0];
+                if let Err(error) = body.seek(SeekFrom::Start(start)) {
+                    println!("{}", error);
+                    res.status(500);
+                    return res;
+                }
+                res.header(
+                    "content-range",
+                    format!("bytes {}-{}/{}", start, end, length),
+                );
+                res.body_stream_sized(body.take(end - start + 1), end - start + 1);
+                res.header("content-type", file_type);
+            } else {
+                // Several ranges are sent as parts of a multipart/byteranges body
+                let boundary = multipart_boundary();
+                let mut segments = VecDeque::new();
+                let mut body_length = 0;
+                for (start, end) in ranges {
+                    let part_header = format!(
+                        "--{}\r\ncontent-type: {}\r\ncontent-range: bytes {}-{}/{}\r\n\r\n",
+                        boundary, file_type, start, end, length
+                    );
+                    body_length += part_header.len() as u64 + (end - start + 1) + 2;
+                    segments.push_back(Segment::Bytes(part_header.into_bytes()));
+                    segments.push_back(Segment::File(start, end - start + 1));
+                    segments.push_back(Segment::Bytes(b"\r\n".to_vec()));
+                }
+                let closing = format!("--{}--\r\n", boundary);
+                body_length += closing.len() as u64;
+                segments.push_back(Segment::Bytes(closing.into_bytes()));
+                res.body_stream_sized(
+                    MultipartReader {
+                        body,
+                        segments,
+                        started: false,
+                    },
+                    body_length,
+                );
+                res.header(
+                    "content-type",
+                    format!("multipart/byteranges; boundary={}", boundary),
+                );
+            }
+        }
+    }
+    return res;
+}
+
+impl StaticFile {
+    /// Reads the metadata of a file on disk. Returns None if the path is not a file
+    fn from_disk(path: &Path) -> Option<StaticFile> {
+        let metadata = fs::metadata(path).ok()?;
+        if !metadata.is_file() {
+            return None;
+        }
+        return Some(StaticFile {
+            content: Content::Disk(path.to_path_buf()),
+            length: metadata.len(),
+            modified: metadata.modified().ok(),
+            etag: None,
+        });
+    }
+
+    /// Opens the contents of the file for reading
+    fn open(&self) -> io::Result<Box<dyn FileBody>> {
+        match &self.content {
+            Content::Disk(path) => Ok(Box::new(fs::File::open(path)?)),
+            Content::Embedded(contents) => Ok(Box::new(Cursor::new(*contents))),
+        }
+    }
+}
+
+/// A part of a multipart/byteranges body, either bytes such as the part headers or a range of the file given as start and length
+enum Segment {
+    Bytes(Vec<u8>),
+    File(u64, u64),
+}
+
+/// Reads the segments of a multipart/byteranges body in order, seeking to every range of the file as it is reached
+struct MultipartReader {
+    body: Box<dyn FileBody>,
+    segments: VecDeque<Segment>,
+    // Set when the file has been seeked to the start of the current file segment
+    started: bool,
+}
+
+impl Read for MultipartReader {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let read = match self.segments.front_mut() {
+                None => return Ok(0),
+                Some(Segment::Bytes(bytes)) => {
+                    let amount = buffer.len().min(bytes.len());
+                    buffer[..amount].copy_from_slice(&bytes[..amount]);
+                    bytes.drain(..amount);
+                    amount
+                }
+                Some(Segment::File(start, remaining)) => {
+                    if !self.started {
+                        self.body.seek(SeekFrom::Start(*start))?;
+                        self.started = true;
+                    }
+                    let limit = buffer.len().min(*remaining as usize);
+                    let read = self.body.read(&mut buffer[..limit])?;
+                    if read == 0 && *remaining > 0 {
+                        return Err(io::Error::new(
+                            io::ErrorKind::UnexpectedEof,
+                            "File ended before the requested range",
+                        ));
+                    }
+                    *remaining -= read as u64;
+                    read
+                }
+            };
+            let finished = match self.segments.front() {
+                Some(Segment::Bytes(bytes)) => bytes.is_empty(),
+                Some(Segment::File(_, remaining)) => *remaining == 0,
+                None => true,
+            };
+            if finished {
+                self.segments.pop_front();
+                self.started = false;
+            }
+            if read > 0 || buffer.is_empty() {
+                return Ok(read);
+            }
+        }
+    }
+}
+
+/// Check if an accept-encoding header such as "gzip, deflate, br;q=0.8" accepts the encoding. Encodings with q=0 are refused, * accepts every encoding that is not listed
+fn accepts_encoding(accept_encoding: &str, encoding: &str) -> bool {
+    let mut wildcard = false;
+    for item in accept_encoding.split(',') {
+        let mut parts = item.split(';');
+        let name = parts.next().unwrap_or_default().trim();
+        let mut quality = 1.0;
+        for parameter in parts {
+            if let Some(value) = parameter.trim().strip_prefix("q=") {
+                quality = value.trim().parse().unwrap_or(0.0);
+            }
+        }
+        if name.eq_ignore_ascii_case(encoding) {
+            return quality > 0.0;
+        }
+        if name == "*" {
+            wildcard = quality > 0.0;
+        }
+    }
+    wildcard
+}
+
+/// The etag and last-modified validators of a file, derived from its size and modification time
+struct Validators {
+    etag: Option<String>,
+    last_modified: Option<String>,
+}
+
+impl Validators {
+    fn new(file: &StaticFile) -> Validators {
+        let modified = match file.modified {
+            Some(modified) => modified,
+            None => {
+                return Validators {
+                    etag: file.etag.clone(),
+                    last_modified: None,
+                }
+            }
+        };
+        let nanos = match modified.duration_since(UNIX_EPOCH) {
+            Ok(duration) => duration.as_nanos(),
+            Err(_error) => 0,
+        };
+        return Validators {
+            etag: Some(format!("\"{:x}-{:x}\"", nanos, file.length)),
+            last_modified: Some(http_date::format(modified)),
+        };
+    }
+
+    /// Check if the client already has the current version of the file. If-none-match takes precedence over if-modified-since
+    fn not_modified(&self, request: &Request) -> bool {
+        if let Some(if_none_match) = request.headers.get("if-none-match") {
+            let etag = match &self.etag {
+                Some(etag) => etag,
+                None => return false,
+            };
+            // Weak comparison, W/"x" matches "x"
+            return if_none_match.split(',').any(|tag| {
+                let tag = tag.trim();
+                tag == "*" || tag.trim_start_matches("W/") == etag
+            });
+        }
+        if let Some(if_modified_since) = request.headers.get("if-modified-since") {
+            let last_modified = match &self.last_modified {
+                Some(last_modified) => last_modified,
+                None => return false,
+            };
+            return match (
+                http_date::parse(if_modified_since),
+                http_date::parse(last_modified),
+            ) {
+                (Some(since), Some(modified)) => modified <= since,
+                _ => false,
+            };
+        }
+        return false;
+    }
+
+    /// Check if the range header should be used. A request with an if-range header only gets part of the file if the etag or date in the header matches the current version of the file
+    fn if_range_matches(&self, request: &Request) -> bool {
+        let if_range = match request.headers.get("if-range") {
+            Some(if_range) => if_range.trim(),
+            None => return true,
+        };
+        // Strong comparison, weak etags never match
+        if if_range.starts_with('"') {
+            return self.etag.as_deref() == Some(if_range);
+        }
+        match (http_date::parse(if_range), &self.last_modified) {
+            (Some(date), Some(last_modified)) => http_date::format(date) == *last_modified,
+            _ => false,
+        }
+    }
+}
+
+/// Hashes bytes with 64 bit FNV-1a, used for the etags of embedded files
+fn fnv_hash(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+/// Creates a boundary for multipart bodies that is unique for every response
+fn multipart_boundary() -> String {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let nanos = match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(duration) => duration.as_nanos(),
+        Err(_error) => 0,
+    };
+    let count = COUNTER.fetch_add(1, Ordering::Relaxed);
+    format!("spot-{:x}-{:x}", nanos, count)
+}
+
+/// Decodes percent encoded characters in a url path, for example %20 -> " ". Returns None if the encoding is invalid or decodes to a null byte
+fn percent_decode(input: &str) -> Option<String> {
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        if bytes[index] == b'%' {
+            let hex = input.get(index + 1..index + 3)?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            index += 3;
+        } else {
+            decoded.push(bytes[index]);
+            index += 1;
+        }
+    }
+    if decoded.contains(&0) {
+        return None;
+    }
+    String::from_utf8(decoded).ok()
+}
+
+/// Percent encodes every character in a path segment that is not unreserved in urls, for example " " -> %20
+fn percent_encode(input: &str) -> String {
+    let mut encoded = String::with_capacity(input.len());
+    for byte in input.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+/// Escapes the characters that have a special meaning in html
+fn html_escape(input: &str) -> String {
+    input
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    /// Creates an empty directory for a test, with a root directory to mount and a secret file outside of it
+    fn test_directory(name: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!("spot-{}-{}", std::process::id(), name));
+        let _result = fs::remove_dir_all(&directory);
+        fs::create_dir_all(directory.join("root/dir")).unwrap();
+        fs::write(directory.join("secret.txt"), "secret").unwrap();
+        fs::write(directory.join("root/file.txt"), "hello world").unwrap();
+        fs::write(directory.join("root/index.html"), "<p>app</p>").unwrap();
+        fs::write(directory.join("root/dir/index.html"), "<p>dir</p>").unwrap();
+        fs::write(directory.join("root/app.js"), "js").unwrap();
+        fs::write(directory.join("root/app.js.gz"), "gz").unwrap();
+        fs::write(directory.join("root/app.js.br"), "br").unwrap();
+        return directory;
+    }
+
+    fn request(url: &str, headers: &[(&str, &str)]) -> Request {
+        return Request::new(
+            String::from(url),
+            HashMap::new(),
+            Vec::new(),
+            String::from("1.1"),
+            String::from("GET"),
+            headers
+                .iter()
+                .map(|(name, value)| (String::from(*name), String::from(*value)))
+                .collect(),
+        );
+    }
+
+    fn not_found() -> Response {
+        return Response::new(404, Vec::new(), HashMap::new());
+    }
+
+    fn validators() -> Validators {
+        return Validators {
+            etag: Some(String::from("\"abc\"")),
+            last_modified: Some(String::from("Sun, 06 Nov 1994 08:49:37 GMT")),
+        };
+    }
+
+    #[test]
+    fn finds_files_inside_the_root() {
+        let directory = test_directory("inside");
+        let mount = StaticMount::new("/", &directory.join("root"));
+        assert!(mount.contains("/file.txt"));
+        assert!(!mount.contains("/dir/../file.txt"));
+        assert!(mount.contains("/%66ile.txt"));
+        assert!(mount.contains("//dir/./index.html"));
+        assert!(!mount.contains("/missing.txt"));
+    }
+
+    #[test]
+    fn rejects_paths_outside_the_root() {
+        let directory = test_directory("outside");
+        let mount = StaticMount::new("/", &directory.join("root"));
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(directory.join("secret.txt"), directory.join("root/link"))
+            .unwrap();
+        for url in [
+            "/../secret.txt",
+            "/%2e%2e/secret.txt",
+            "/%2E%2E/secret.txt",
+            "/dir/..%2f..%2fsecret.txt",
+            "/link",
+        ] {
+            assert!(mount.lookup(url).is_none(), "url {}", url);
+        }
+    }
+
+    #[test]
+    fn redirects_directories_to_the_normalized_path() {
+        let directory = test_directory("redirect");
+        let mount = StaticMount::new("/files", &directory.join("root"));
+        for (url, location) in [
+            ("/files/dir", "/files/dir/"),
+            ("/files/dir?a=b&c", "/files/dir/?a=b&c"),
+            ("/files//dir", "/files/dir/"),
+            ("/files/./dir?x", "/files/dir/?x"),
+        ] {
+            let response = mount.serve(&request(url, &[]), not_found());
+            assert_eq!(response.status, 301, "url {}", url);
+            assert_eq!(response.headers["location"], location, "url {}", url);
+        }
+        let mount = StaticMount::new("/", &directory.join("root"));
+        let response = mount.serve(&request("//dir", &[]), not_found());
+        assert_eq!(response.headers["location"], "/dir/");
+    }
+
+    #[test]
+    fn serves_accepted_precompressed_variants() {
+        let directory = test_directory("precompressed");
+        let mut mount = StaticMount::new("/", &directory.join("root"));
+        let encoding = |mount: &StaticMount, accept_encoding: &str| {
+            let request = request("/app.js", &[("accept-encoding", accept_encoding)]);
+            return mount
+                .precompressed_variant("/app.js", &request)
+                .map(|(_file, encoding)| encoding);
+        };
+        assert_eq!(encoding(&mount, "br, gzip"), None);
+        mount.precompressed(true);
+        assert_eq!(encoding(&mount, "gzip, br"), Some("br"));
+        assert_eq!(encoding(&mount, "br;q=0, gzip"), Some("gzip"));
+        assert_eq!(encoding(&mount, "br;q=0, gzip;q=0.0"), None);
+        assert_eq!(encoding(&mount, "*;q=0"), None);
+        assert_eq!(encoding(&mount, "*, br;q=0"), Some("gzip"));
+        assert_eq!(encoding(&mount, "identity"), None);
+    }
+
+    #[test]
+    fn serves_the_fallback_only_to_html_requests() {
+        let directory = test_directory("fallback");
+        let mut mount = StaticMount::new("/", &directory.join("root"));
+        mount.fallback("index.html");
+        let status = |accept: Option<&str>| {
+            let headers: Vec<(&str, &str)> = accept
+                .map(|accept| ("accept", accept))
+                .into_iter()
+                .collect();
+            return mount
+                .serve(&request("/missing/page", &headers), not_found())
+                .status;
+        };
+        assert_eq!(status(Some("text/html,application/xhtml+xml")), 200);
+        assert_eq!(status(Some("TEXT/HTML;q=0.5")), 200);
+        assert_eq!(status(Some("text/html;q=0, */*")), 404);
+        assert_eq!(status(Some("application/json")), 404);
+        assert_eq!(status(Some("*/*")), 404);
+        assert_eq!(status(None), 404);
+    }
+
+    #[test]
+    fn compares_conditional_request_validators() {
+        let validators = validators();
+        let not_modified =
+            |headers: &[(&str, &str)]| validators.not_modified(&request("/", headers));
+        assert!(not_modified(&[("if-none-match", "\"abc\"")]));
+        assert!(not_modified(&[("if-none-match", "\"x\", W/\"abc\"")]));
+        assert!(not_modified(&[("if-none-match", "*")]));
+        assert!(!not_modified(&[("if-none-match", "\"abcd\"")]));
+        assert!(not_modified(&[(
+            "if-modified-since",
+            "Sun, 06 Nov 1994 08:49:37 GMT"
+        )]));
+        assert!(not_modified(&[(
+            "if-modified-since",
+            "Mon, 07 Nov 1994 08:49:37 GMT"
+        )]));
+        assert!(!not_modified(&[(
+            "if-modified-since",
+            "Sun, 06 Nov 1994 08:49:36 GMT"
+        )]));
+        assert!(!not_modified(&[("if-modified-since", "yesterday")]));
+        // If-none-match takes precedence over if-modified-since
+        assert!(!not_modified(&[
+            ("if-none-match", "\"other\""),
+            ("if-modified-since", "Sun, 06 Nov 1994 08:49:37 GMT"),
+        ]));
+        assert!(!not_modified(&[]));
+    }
+
+    #[test]
+    fn compares_if_range_validators() {
+        let validators = validators();
+        let matches =
+            |headers: &[(&str, &str)]| validators.if_range_matches(&request("/", headers));
+        assert!(matches(&[]));
+        assert!(matches(&[("if-range", "\"abc\"")]));
+        assert!(!matches(&[("if-range", "W/\"abc\"")]));
+        assert!(!matches(&[("if-range", "\"other\"")]));
+        assert!(matches(&[("if-range", "Sun, 06 Nov 1994 08:49:37 GMT")]));
+        assert!(!matches(&[("if-range", "Mon, 07 Nov 1994 08:49:37 GMT")]));
+        assert!(!matches(&[("if-range", "yesterday")]));
+    }
+
+    #[test]
+    fn writes_multipart_bodies_of_the_declared_length() {
+        let directory = test_directory("multipart");
+        let path = directory.join("root/file.txt");
+        let request = request("/file.txt", &[("range", "bytes=0-1,4-6,-2")]);
+        let response = serve_file(&path, &request, not_found());
+        assert_eq!(response.status, 206);
+        let content_length: usize = response.headers["content-length"].parse().unwrap();
+        let boundary = String::from(
+            response.headers["content-type"]
+                .strip_prefix("multipart/byteranges; boundary=")
+                .unwrap(),
+        );
+        let mut http = Vec::new();
+        response.write_to(&mut http, "1.1", false).unwrap();
+        let http = String::from_utf8(http).unwrap();
+        let body = &http[http.find("\r\n\r\n").unwrap() + 4..];
+        assert_eq!(body.len(), content_length);
+        assert!(body.contains("content-range: bytes 4-6/11\r\n\r\no w\r\n"));
+        assert!(body.contains("content-range: bytes 9-10/11\r\n\r\nld\r\n"));
+        assert!(body.ends_with(&format!("--{}--\r\n", boundary)));
+    }
+
+    #[test]
+    fn dates_without_a_modification_time_are_never_fresh() {
+        let file = StaticFile {
+            content: Content::Embedded(b"embedded"),
+            length: 8,
+            modified: None,
+            etag: Some(String::from("\"e\"")),
+        };
+        let validators = Validators::new(&file);
+        let since = http_date::format(UNIX_EPOCH + Duration::from_secs(1700000000));
+        assert!(!validators.not_modified(&request("/", &[("if-modified-since", &since)])));
+        assert!(validators.not_modified(&request("/", &[("if-none-match", "\"e\"")])));
+    }
+}
@@ -1,73 +1,237 @@
-use crate::http_parser::HttpParser;
-use crate::request::Request;
-use crate::response::Response;
-use std::collections::HashMap;
-use std::io::Write;
+use crate::app::App;
+use crate::config::Backpressure;
+use crate::connection::{handle_connection, reject_connection};
 use std::net::TcpStream;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::TrySendError;
 use std::sync::{mpsc, Arc, Mutex};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 pub struct ThreadPool {
     // A vector containing worker threads equal to the amount specified in new()
     workers: Vec<Worker>,
-    // A channel for forwarding jobs to the worker threads
-    sender: mpsc::Sender<Message>,
+    // A bounded channel for forwarding jobs to the worker threads
+    sender: mpsc::SyncSender<Message>,
+    // The amount of open connections, both those being handled and those waiting in the channel
+    open_connections: Arc<AtomicUsize>,
+    // Shared with the workers, holds the queue and connection limits
+    app: Arc<App>,
+    // The receiving end of the channel, kept so that workers can be respawned
+    receiver: Arc<Mutex<mpsc::Receiver<Message>>>,
 }
 
 enum Message {
-    NewJob(TcpStream),
+    NewJob(Connection),
+    Task(Box<dyn Task>),
     Terminate,
 }
 
+/// Work other than a whole connection that is handed to the workers, such as a request read by the event loop
+pub trait Task: Send {
+    /// Does the work on a worker thread
+    fn run(self: Box<Self>, app: &App);
+    /// Called instead of run if the queue is full and the backpressure setting rejects new work
+    fn reject(self: Box<Self>, retry_after: u64);
+}
+
+/// An accepted connection, counted as open until it is dropped
+struct Connection {
+    stream: TcpStream,
+    open_connections: Arc<AtomicUsize>,
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.open_connections.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
 impl ThreadPool {
     /// Create a new ThreadPool.
     ///
-    /// The size is the number of threads in the pool.
+    /// The size is the number of threads in the pool. Accepted connections wait for a free thread in a queue that holds at most the queue depth of the app config
     ///
     /// # Panics
     ///
     /// The `new` function will panic if the size is zero.
-    pub fn new(
-        size: usize,
-        routes: HashMap<String, fn(Request, Response) -> Response>,
-        middleware: Vec<(String, fn(Request, Response) -> (Request, Response, bool))>,
-    ) -> ThreadPool {
+    pub fn new(size: usize, app: Arc<App>) -> ThreadPool {
         assert!(size > 0);
 
-        let (sender, receiver) = mpsc::channel();
+        let (sender, receiver) = mpsc::sync_channel(app.config.queue_depth);
 
         let receiver = Arc::new(Mutex::new(receiver));
 
         let mut workers = Vec::with_capacity(size);
 
         for id in 0..size {
-            let routes_clone = routes.clone();
-            let middleware_clone = middleware.clone();
-            workers.push(Worker::new(
-                id,
-                Arc::clone(&receiver),
-                routes_clone,
-                middleware_clone,
-            ));
+            workers.push(Worker::new(id, Arc::clone(&receiver), Arc::clone(&app)));
         }
 
-        ThreadPool { workers, sender }
+        ThreadPool {
+            workers,
+            sender,
+            open_connections: Arc::new(AtomicUsize::new(0)),
+            app,
+            receiver,
+        }
     }
 
-    pub fn execute(&self, stream: TcpStream) {
-        match self.sender.send(Message::NewJob(stream)) {
-            Ok(_) => {}
+    /// Hands the connection to a free worker, or queues it until a worker is free.
+    ///
+    /// If the queue is full or there are too many open connections the connection is rejected with 503 Service Unavailable, or the call blocks until there is room, depending on the backpressure setting
+    pub fn execute(&mut self, stream: TcpStream) {
+        self.respawn_dead_workers();
+        let app = Arc::clone(&self.app);
+        let config = &app.config;
+        if let Some(max_connections) = config.max_connections {
+            while self.open_connections.load(Ordering::SeqCst) >= max_connections {
+                match config.backpressure {
+                    Backpressure::Reject(retry_after) => {
+                        reject_connection(&stream, retry_after);
+                        return;
+                    }
+                    // The connection is turned away if the server shuts down while it waits
+                    Backpressure::Block if self.app.shutdown.is_shutdown() => {
+                        reject_connection(&stream, 1);
+                        return;
+                    }
+                    Backpressure::Block => thread::sleep(Duration::from_millis(10)),
+                }
+            }
+        }
+        self.open_connections.fetch_add(1, Ordering::SeqCst);
+        let connection = Connection {
+            stream,
+            open_connections: Arc::clone(&self.open_connections),
+        };
+        // A blocking send only gives the connection back when the server shuts down
+        if let Some(Message::NewJob(connection)) = self.send(Message::NewJob(connection)) {
+            let retry_after = match config.backpressure {
+                Backpressure::Reject(retry_after) => retry_after,
+                Backpressure::Block => 1,
+            };
+            reject_connection(&connection.stream, retry_after);
+        }
+    }
+
+    /// Hands the task to a free worker, or queues it until a worker is free. If the queue is full the task is rejected, or returned so that it can be handed over again later, depending on the backpressure setting. Never blocks, so that an event loop can keep serving its other connections
+    pub fn execute_task(&mut self, task: Box<dyn Task>) -> Option<Box<dyn Task>> {
+        self.respawn_dead_workers();
+        match self.sender.try_send(Message::Task(task)) {
+            Ok(_) => None,
+            Err(TrySendError::Full(Message::Task(task))) => match self.app.config.backpressure {
+                Backpressure::Reject(retry_after) => {
+                    task.reject(retry_after);
+                    None
+                }
+                Backpressure::Block => Some(task),
+            },
             Err(error) => {
                 println!("{}", error);
+                None
             }
-        };
+        }
+    }
+
+    /// Puts the message in the queue. Returns the message if the queue is full and the backpressure setting rejects it, or the server shuts down while waiting for room
+    fn send(&mut self, mut message: Message) -> Option<Message> {
+        loop {
+            match self.sender.try_send(message) {
+                Ok(_) => return None,
+                Err(TrySendError::Full(full_message)) => match self.app.config.backpressure {
+                    Backpressure::Reject(_) => return Some(full_message),
+                    Backpressure::Block if self.app.shutdown.is_shutdown() => {
+                        return Some(full_message)
+                    }
+                    Backpressure::Block => {
+                        // Workers that died while the queue is full would otherwise never make room
+                        thread::sleep(Duration::from_millis(10));
+                        self.respawn_dead_workers();
+                        message = full_message;
+                    }
+                },
+                Err(error) => {
+                    println!("{}", error);
+                    return None;
+                }
+            }
+        }
+    }
+
+    /// Replaces workers whose thread has ended. Panics in handlers are caught, so workers only end on their own if something else panics, such as the panic hook
+    fn respawn_dead_workers(&mut self) {
+        for worker in &mut self.workers {
+            let finished = match &worker.thread {
+                Some(thread) => thread.is_finished(),
+                None => false,
+            };
+            if !finished {
+                continue;
+            }
+            if let Some(thread) = worker.thread.take() {
+                // The thread ended with a panic, which has already been printed
+                let _result = thread.join();
+            }
+            println!(
+                "Worker {} stopped unexpectedly, starting a new worker",
+                worker.id
+            );
+            *worker = Worker::new(worker.id, Arc::clone(&self.receiver), Arc::clone(&self.app));
+        }
+    }
+
+    /// Stops the threads gracefully, meaning that they finish their current tasks and the connections waiting in the queue, then end themselves.
+    ///
+    /// Waits up to the timeout for the workers to finish, workers that are still busy after the timeout are left running in the background
+    pub fn shutdown(&mut self, timeout: Duration) {
+        // Every worker has to be alive to take its terminate message from the queue
+        self.respawn_dead_workers();
+        let deadline = Instant::now() + timeout;
+        println!("Sending terminate message to all workers.");
+        // The queue can be full of connections that are still waiting, so the messages are only sent while there is time left. Workers that do not get one end when the pool is dropped
+        let mut unsent = self.workers.len();
+        while unsent > 0 {
+            match self.sender.try_send(Message::Terminate) {
+                Ok(_) => unsent -= 1,
+                Err(TrySendError::Full(_)) if Instant::now() < deadline => {
+                    thread::sleep(Duration::from_millis(10));
+                }
+                Err(TrySendError::Full(_)) => break,
+                Err(error) => {
+                    println!("{}", error);
+                    break;
+                }
+            }
+        }
+
+        for mut worker in self.workers.drain(..) {
+            if let Some(thread) = worker.thread.take() {
+                while !thread.is_finished() && Instant::now() < deadline {
+                    thread::sleep(Duration::from_millis(10));
+                }
+                if !thread.is_finished() {
+                    println!(
+                        "Worker {} did not finish before the shutdown timeout",
+                        worker.id
+                    );
+                    continue;
+                }
+                match thread.join() {
+                    Ok(_) => {}
+                    Err(_error) => println!("Failed to join thread"),
+                }
+            }
+        }
     }
 }
 
-/// Stops the threads gracefully, meaning that they finish their current tasks and then end themselves. Currently not in use
+/// Stops the threads gracefully if the pool is dropped without being shut down, waiting for their current tasks without a timeout
 impl Drop for ThreadPool {
     fn drop(&mut self) {
+        if self.workers.is_empty() {
+            return;
+        }
         println!("Sending terminate message to all workers.");
 
         for _ in &self.workers {
@@ -100,88 +264,30 @@ struct Worker {
 impl Worker {
     /// Create a new Worker.
     ///
-    /// The worker stores clones of the routes and middleware hashmaps for handling requests. The reveiver is used to forward jobs into the thread.
-    fn new(
-        id: usize,
-        receiver: Arc<Mutex<mpsc::Receiver<Message>>>,
-        routes: HashMap<String, fn(Request, Response) -> Response>,
-        middleware: Vec<(String, fn(Request, Response) -> (Request, Response, bool))>,
-    ) -> Worker {
+    /// The worker shares the routes, middleware and state of the app with the other workers for handling requests. The reveiver is used to forward jobs into the thread.
+    fn new(id: usize, receiver: Arc<Mutex<mpsc::Receiver<Message>>>, app: Arc<App>) -> Worker {
         let thread = thread::spawn(move || 'outer: loop {
-            // Receive message from main thread
-            let lock = match receiver.lock() {
-                Ok(lock) => lock,
-                Err(error) => {
-                    println!("{}", error);
-                    continue 'outer;
-                }
+            // Receive message from main thread, the lock is released before the job is handled so that the other workers can receive jobs
+            // A worker that panicked never holds the lock, so a poisoned lock is still safe to use
+            let message = match receiver.lock() {
+                Ok(lock) => lock.recv(),
+                Err(poisoned) => poisoned.into_inner().recv(),
             };
-            let message = match lock.recv() {
+            let message = match message {
                 Ok(message) => message,
                 Err(error) => {
+                    // The pool has been dropped
                     println!("{}", error);
-                    continue 'outer;
+                    break 'outer;
                 }
             };
 
             // Handle job
             match message {
-                Message::NewJob(stream) => {
-                    let mut response = Response::new(404, Vec::new(), HashMap::new());
-                    let parse_result = HttpParser::parse(&stream);
-                    let mut request = match parse_result {
-                        Ok(request) => request,
-                        Err(error) => {
-                            println!("HTTP Parser Error: {}", error);
-                            response.status(400);
-                            write_response(stream, response);
-                            continue 'outer; // Skip to next iteration
-                        }
-                    };
-                    // Remove params
-                    let request_wo_params = match request.url.split("?").next() {
-                        Some(url) => url,
-                        None => {
-                            response.status(400);
-                            write_response(stream, response);
-                            continue 'outer; // Skip to next iteration
-                        }
-                    };
-                    let mut request_route = String::from(request_wo_params);
-                    // Remove trailing / so that pathing is agnostic towards /example/ or /example
-                    let last_char = match request_route.pop() {
-                        Some(character) => character,
-                        None => {
-                            response.status(500);
-                            response.body("failed to parse http");
-                            write_response(stream, response);
-                            continue 'outer;
-                        }
-                    };
-                    if last_char != '/' || request_route.len() == 0 {
-                        request_route.push(last_char)
-                    }
-                    if routes.contains_key(&request_route) {
-                        // Route through middleware
-                        for mid in &middleware {
-                            if mid.0.len() > request_route.len() {
-                                break;
-                            };
-                            if mid.0 == request_route[..mid.0.len()] {
-                                let answer = mid.1(request, response);
-                                response = answer.1;
-                                // If the middleware rejects the request we return the response
-                                if !answer.2 {
-                                    write_response(stream, response);
-                                    continue 'outer;
-                                }
-                                request = answer.0;
-                            }
-                        }
-                        response = routes[&request_route](request, response);
-                    }
-                    write_response(stream, response);
+                Message::NewJob(connection) => {
+                    handle_connection(&connection.stream, &app);
                 }
+                Message::Task(task) => task.run(&app),
                 Message::Terminate => {
                     println!("Worker {} was told to terminate.", id);
                     break;
@@ -193,16 +299,5 @@ impl Worker {
             id,
             thread: Some(thread),
         };
-        // Writes a tcp response to the client
-        fn write_response(mut stream: TcpStream, response: Response) {
-            let five_seconds = Duration::new(5, 0);
-            stream
-                .set_write_timeout(Some(five_seconds))
-                .expect("set_write_timeout call failed");
-            match stream.write(&response.to_http()) {
-                Ok(_) => {}
-                Err(e) => println!("Failed sending response: {}", e),
-            }
-        }
     }
 }
@@ -0,0 +1,4 @@
+fn main() {
+    // Embed the public directory so it can be served without the files on disk
+    spot::embed::generate("public").unwrap();
+}
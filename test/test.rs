@@ -1,12 +1,36 @@
 use spot;
 use spot::request::Request;
 use spot::response::Response;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+// The public directory embedded into the binary by build.rs
+static ASSETS: &[(&str, &[u8])] = spot::include_assets!("public");
+
+// Application state is stored and retrieved by type
+struct Config {
+    name: String,
+}
 
 fn main() {
     // Create a spot app with 2 worker threads
     let mut app = spot::Spot::new(2);
     // Use a directory called public in the project root to serve static files
-    app.public("public");
+    app.public("public").precompressed(true);
+    // Directories can also be served under a url prefix, here with a listing of the files
+    app.public_at("/docs", "public/new_folder")
+        .cache_control("public, max-age=60")
+        .listing(true);
+    // Single page apps can answer every html request that matches no file or route with their index.html
+    app.public_at("/app", "public").fallback("index.html");
+    // Files embedded at compile time are served like a directory on disk
+    app.embedded_at("/embedded", ASSETS);
+    // Ctrl+C stops accepting connections and lets the current requests finish before bind returns
+    app.shutdown_on_signals(true);
+    // Idle keep-alive connections wait in an epoll event loop instead of holding a worker thread, linux only
+    app.event_loop(true);
+    // Content types can be added or overridden by file ending
+    app.mime_type("glb", "model/gltf-binary");
 
     app.middle(
         "/post/",
@@ -21,11 +45,11 @@ fn main() {
         },
     );
 
-    // Redirect
-    app.route("/", |req: Request, mut res: Response| -> Response {
+    // Redirect, GET / is answered with public/index.html
+    app.route("/home", |req: Request, mut res: Response| -> Response {
         if req.method == "GET" {
             res.status(301);
-            res.header("Location", "/index.html");
+            res.header("Location", "/");
         }
         return res;
     });
@@ -55,6 +79,41 @@ fn main() {
         };
     });
 
+    // Path parameters are captured into req.path_params. Routes added with get only answer GET and HEAD requests,
+    // other methods get a 405 Method Not Allowed response
+    app.get(
+        "/user/:id/posts/:post_id",
+        |req: Request, mut res: Response| -> Response {
+            res.status(200);
+            res.body(format!(
+                "Post {} by user {}",
+                req.path_params["post_id"], req.path_params["id"]
+            ));
+            return res;
+        },
+    );
+
+    // Closures can capture state that is shared between the worker threads
+    let visits = Arc::new(AtomicUsize::new(0));
+    app.get("/visits", move |_req: Request, mut res: Response| -> Response {
+        let count = visits.fetch_add(1, Ordering::SeqCst) + 1;
+        res.status(200);
+        res.body(format!("Visits: {}", count));
+        return res;
+    });
+
+    // State added to the app is avalible to all routes and middleware through req.state()
+    app.state(Config {
+        name: String::from("Spot test server"),
+    });
+    app.get("/name", |req: Request, mut res: Response| -> Response {
+        if let Some(config) = req.state::<Config>() {
+            res.status(200);
+            res.body(&config.name);
+        }
+        return res;
+    });
+
     // Add a POST endpoint to /post
     app.route("/post/", |req: Request, mut res: Response| -> Response {
         // Spot does not have JSON serilization built inn,
@@ -73,7 +132,8 @@ fn main() {
         };
     });
 
-    // Bind the spot app to port 3000 on the local IP adress
-    let err = app.bind("127.0.0.1:3000");
-    println!("{}", err);
+    // Bind the spot app to port 3000 on the local IP adress, bind returns when the server shuts down
+    if let Err(error) = app.bind("127.0.0.1:3000") {
+        println!("{}", error);
+    }
 }